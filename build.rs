@@ -0,0 +1,205 @@
+//! Fits the sigmoid coefficient table `rgb_to_spectrum` looks up, so renders
+//! don't pay for the fit at startup.
+
+use std::{env, fs, path::Path, sync::LazyLock};
+
+#[path = "src/cie.rs"]
+mod cie;
+
+use crate::cie::{
+    CIE_Y_INTEGRAL, LAMBDA_MAX, LAMBDA_MIN, SigmoidPolynomial, d65_normalized, xyz,
+    xyz_to_linear_srgb,
+};
+
+type Rgb = [f64; 3];
+
+/// Per-axis resolution of the coefficient table.
+const RES: usize = 32;
+const FIT_STEP: f64 = 5.0;
+
+/// Per-wavelength contributions to linear sRGB under D65, normalised so a
+/// constant unit spectrum maps exactly to white.
+static FIT_WEIGHTS: LazyLock<Vec<(f64, Rgb)>> = LazyLock::new(|| {
+    let mut weights = Vec::new();
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        let scale = d65_normalized(lambda) * FIT_STEP;
+        let rgb = xyz_to_linear_srgb(xyz(lambda)).map(|c| c * scale / *CIE_Y_INTEGRAL);
+        weights.push((lambda, rgb));
+        lambda += FIT_STEP;
+    }
+
+    let total = weights
+        .iter()
+        .fold([0.0; 3], |acc, (_, w)| [0, 1, 2].map(|i| acc[i] + w[i]));
+    weights
+        .into_iter()
+        .map(|(l, w)| (l, [0, 1, 2].map(|i| w[i] / total[i])))
+        .collect()
+});
+
+fn smoothstep(x: f64) -> f64 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+fn spectrum_to_rgb(c: [f64; 3]) -> Rgb {
+    let s = SigmoidPolynomial { c };
+    FIT_WEIGHTS.iter().fold([0.0; 3], |acc, (lambda, w)| {
+        let value = s.eval(*lambda);
+        [0, 1, 2].map(|i| acc[i] + value * w[i])
+    })
+}
+
+fn sub(a: Rgb, b: Rgb) -> Rgb {
+    [0, 1, 2].map(|i| a[i] - b[i])
+}
+
+fn len(v: Rgb) -> f64 {
+    f64::sqrt(v[0] * v[0] + v[1] * v[1] + v[2] * v[2])
+}
+
+fn solve3(m: [[f64; 3]; 3], b: Rgb) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+
+    let d = det(m);
+    if d.abs() < 1e-15 {
+        return None;
+    }
+
+    // Cramer's rule
+    let mut x = [0.0; 3];
+    for (col, xi) in x.iter_mut().enumerate() {
+        let mut mc = m;
+        for row in 0..3 {
+            mc[row][col] = b[row];
+        }
+        *xi = det(mc) / d;
+    }
+    Some(x)
+}
+
+/// Damped Gauss-Newton fit of sigmoid coefficients reproducing `target`.
+fn fit(target: Rgb, mut c: [f64; 3]) -> [f64; 3] {
+    let mut residual = sub(spectrum_to_rgb(c), target);
+
+    for _ in 0..50 {
+        if len(residual) < 1e-6 {
+            break;
+        }
+
+        let eps = 1e-5;
+        let mut jacobian = [[0.0; 3]; 3];
+        for i in 0..3 {
+            let mut ci = c;
+            ci[i] += eps;
+            let d = sub(sub(spectrum_to_rgb(ci), target), residual).map(|d| d / eps);
+            for row in 0..3 {
+                jacobian[row][i] = d[row];
+            }
+        }
+
+        let Some(step) = solve3(jacobian, residual) else {
+            break;
+        };
+
+        let mut damping = 1.0;
+        loop {
+            let candidate = [
+                c[0] - damping * step[0],
+                c[1] - damping * step[1],
+                c[2] - damping * step[2],
+            ];
+            let candidate_residual = sub(spectrum_to_rgb(candidate), target);
+
+            if len(candidate_residual) < len(residual) || damping < 1e-4 {
+                c = candidate;
+                residual = candidate_residual;
+                break;
+            }
+            damping *= 0.5;
+        }
+    }
+
+    c
+}
+
+fn index(l: usize, k: usize, j: usize, i: usize) -> usize {
+    ((l * RES + k) * RES + j) * RES + i
+}
+
+/// Brightness levels of the table and the coefficients at every entry, indexed
+/// by the largest channel, its level and the two other channels relative to it.
+fn build_table() -> (Vec<f64>, Vec<[f64; 3]>) {
+    let scale: Vec<f64> = (0..RES)
+        .map(|k| smoothstep(smoothstep(k as f64 / (RES - 1) as f64)))
+        .collect();
+    let mut coeffs = vec![[0.0; 3]; 3 * RES * RES * RES];
+
+    // Sweep brightness outwards from a mid value, warm-starting every fit
+    // from the neighbouring brightness level as in Jakob & Hanika.
+    let start = RES / 5;
+    let order: Vec<usize> = (start..RES).chain((0..start).rev()).collect();
+
+    for l in 0..3 {
+        let mut previous = vec![[0.0; 3]; RES * RES];
+
+        for (n, &k) in order.iter().enumerate() {
+            if n == RES - start {
+                // Restart the downward sweep from the starting level.
+                for j in 0..RES {
+                    for i in 0..RES {
+                        previous[i + RES * j] = coeffs[index(l, start, j, i)];
+                    }
+                }
+            }
+
+            let z = scale[k];
+            for j in 0..RES {
+                for i in 0..RES {
+                    let x = i as f64 / (RES - 1) as f64;
+                    let y = j as f64 / (RES - 1) as f64;
+
+                    let mut rgb = [0.0; 3];
+                    rgb[l] = z;
+                    rgb[(l + 1) % 3] = x * z;
+                    rgb[(l + 2) % 3] = y * z;
+
+                    let c = fit(rgb, previous[i + RES * j]);
+                    previous[i + RES * j] = c;
+                    coeffs[index(l, k, j, i)] = c;
+                }
+            }
+        }
+    }
+
+    (scale, coeffs)
+}
+
+fn main() {
+    for source in ["build.rs", "src/cie.rs"] {
+        println!("cargo::rerun-if-changed={source}");
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+    let (scale, coeffs) = build_table();
+
+    let bytes: Vec<u8> = coeffs
+        .iter()
+        .flatten()
+        .flat_map(|c| c.to_le_bytes())
+        .collect();
+    fs::write(out_dir.join("rgb_to_spectrum.bin"), bytes).unwrap();
+
+    // `{:?}` prints floats exactly, so the table reads back bit for bit
+    let source = format!(
+        "const RES: usize = {RES};\n\
+         const SCALE: [f64; RES] = {scale:?};\n\
+         static COEFFS: &[u8] = include_bytes!(concat!(env!(\"OUT_DIR\"), \"/rgb_to_spectrum.bin\"));\n"
+    );
+    fs::write(out_dir.join("rgb_to_spectrum.rs"), source).unwrap();
+}
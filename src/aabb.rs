@@ -0,0 +1,44 @@
+use std::ops;
+
+use crate::{point::Point3, ray::Ray, vec3::Vec3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub const fn new(min: Point3, max: Point3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    /// Slab test. Returns the part of `ray_range` for which the ray is inside the box.
+    pub fn hit(&self, ray: &Ray, ray_range: ops::Range<f64>) -> Option<ops::Range<f64>> {
+        let mut t_min = ray_range.start;
+        let mut t_max = ray_range.end;
+
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction()[axis];
+            let mut t0 = (self.min[axis] - ray.origin()[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - ray.origin()[axis]) * inv_d;
+
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+
+            if t_max <= t_min {
+                return None;
+            }
+        }
+
+        Some(t_min..t_max)
+    }
+}
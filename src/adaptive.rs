@@ -0,0 +1,111 @@
+use crate::checkpoint::Checkpointed;
+
+/// Stops sampling a pixel once its estimate is precise enough. A pixel takes
+/// at least `min_samples` and at most the camera's `samples_per_pixel`.
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    /// Largest accepted standard error of a pixel's luminance, relative to
+    /// the luminance itself.
+    pub threshold: f64,
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: u32, threshold: f64) -> Self {
+        Self {
+            min_samples,
+            threshold,
+        }
+    }
+
+    /// Whether pixel `idx` and its neighbours in an image `width` pixels wide
+    /// are all precise enough. Looking at the neighbours too keeps a pixel
+    /// going when its own few samples happen to agree by chance.
+    pub fn converged(&self, stats: &[&PixelStats], width: usize, idx: usize) -> bool {
+        let height = stats.len() / width;
+        let (i, j) = (idx % width, idx / width);
+
+        // Pixels without samples, outside a crop window, don't hold their
+        // neighbours back
+        (j.saturating_sub(1)..(j + 2).min(height)).all(|y| {
+            (i.saturating_sub(1)..(i + 2).min(width)).all(|x| {
+                let stats = stats[y * width + x];
+                stats.count == 0 || self.precise(stats)
+            })
+        })
+    }
+
+    fn precise(&self, stats: &PixelStats) -> bool {
+        if stats.count < 2 {
+            return false;
+        }
+
+        stats.relative_error() <= self.threshold
+    }
+}
+
+const LUMINANCE_FLOOR: f64 = 1e-3;
+
+/// Running mean and variance of a pixel's sample luminance (Welford's method).
+#[derive(Default)]
+pub struct PixelStats {
+    pub count: u32,
+    pub mean: f64,
+    m2: f64,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Combines statistics of two disjoint sets of samples (Chan et al.).
+    pub fn merge(&mut self, other: &PixelStats) {
+        if other.count == 0 {
+            return;
+        }
+
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+        let n = n_a + n_b;
+        let delta = other.mean - self.mean;
+
+        self.mean += delta * n_b / n;
+        self.m2 += other.m2 + delta * delta * n_a * n_b / n;
+        self.count += other.count;
+    }
+
+    /// Standard error of the mean, from the unbiased sample variance.
+    pub fn standard_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+
+        let n = self.count as f64;
+        f64::sqrt(self.m2 / (n - 1.0) / n)
+    }
+
+    /// Standard error relative to the mean. Dark pixels are judged against a
+    /// small floor instead of their mean, so near-black noise doesn't count
+    /// as huge.
+    pub fn relative_error(&self) -> f64 {
+        self.standard_error() / self.mean.max(LUMINANCE_FLOOR)
+    }
+}
+
+impl Checkpointed for PixelStats {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.count.save(out);
+        self.mean.save(out);
+        self.m2.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            count: u32::load(input)?,
+            mean: f64::load(input)?,
+            m2: f64::load(input)?,
+        })
+    }
+}
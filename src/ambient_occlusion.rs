@@ -0,0 +1,64 @@
+use rand::RngCore;
+
+use crate::{
+    hittable::Hittable,
+    integrator::{Background, Integrator},
+    ray::Ray,
+    rbg::Rgb,
+    rgb_to_spectrum::illuminant,
+    vec3::{norm, rand_unit_vec},
+};
+
+/// Shades the first hit by how much of its hemisphere is open, using
+/// cosine-weighted occlusion rays that only look `max_distance` far. Misses
+/// are white. Ignores materials and lights, so it's cheap enough for quick
+/// geometry previews.
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    pub max_distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new(samples: u32, max_distance: f64) -> Self {
+        Self {
+            samples,
+            max_distance,
+        }
+    }
+
+    /// Unoccluded fraction of the hemisphere around the first hit of `ray`,
+    /// in `[0, 1]`.
+    pub fn visibility(&self, ray: &Ray, world: &dyn Hittable, mut rng: &mut dyn RngCore) -> f64 {
+        let Some(h) = world.hit(ray, 0.00001..f64::INFINITY) else {
+            return 1.0;
+        };
+
+        let mut visible = 0.0;
+        for _ in 0..self.samples {
+            // A unit vector offset along the normal gives a cosine-weighted
+            // direction, so every occlusion ray is weighted equally.
+            let mut dir = h.n + rand_unit_vec(&mut rng);
+            if dir.near_zero() {
+                dir = h.n;
+            }
+
+            let occlusion_ray = Ray::new(h.p, norm(dir));
+            visible += world.transmittance(&occlusion_ray, 0.00001..self.max_distance);
+        }
+
+        visible / self.samples as f64
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        _background: &Background,
+        rng: &mut dyn RngCore,
+    ) -> Rgb {
+        let v = self.visibility(&ray, world, rng);
+        illuminant(Rgb::new(v, v, v), ray.wavelengths())
+    }
+}
@@ -0,0 +1,607 @@
+use rand::RngCore;
+
+use crate::{
+    camera::Viewport,
+    hittable::Hittable,
+    integrator::{Aovs, Background, Integrator, Splats},
+    material::Material,
+    point::Point3,
+    ray::Ray,
+    rbg::Rgb,
+    spectrum::SampledWavelengths,
+    vec3::{Vec3, dot, norm, rand_unit_vec},
+};
+
+/// Bidirectional path tracer. Every camera sample traces a path from the
+/// camera and one from a randomly picked area light, connects each prefix of
+/// one to each prefix of the other, and weights the resulting strategies with
+/// multiple importance sampling (balance heuristic).
+///
+/// Area lights emit from their outside only. Light from the background and
+/// from emissive volumes can't be sampled, so it's only found by camera paths.
+pub struct Bdpt {
+    pub max_depth: i32,
+}
+
+impl Bdpt {
+    pub fn new(max_depth: i32) -> Self {
+        Self { max_depth }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+    Medium,
+}
+
+/// A path vertex. Densities are per unit area (solid angle for media) at this
+/// vertex: `pdf_fwd` for being reached by the path that created it, `pdf_rev`
+/// for being reached from the other end instead.
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point3,
+    /// Surface normal on the side the path arrived from, the outward normal
+    /// for lights and the viewing direction for the camera.
+    n: Vec3,
+    /// Direction back along the path that created the vertex.
+    wo: Vec3,
+    mat: Option<Material>,
+    object_id: usize,
+    front_face: bool,
+    beta: Rgb,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    /// Scattered by a specular material, so it can't be connected to.
+    delta: bool,
+    /// The path only carries its hero wavelength from here on.
+    single: bool,
+}
+
+impl Vertex {
+    fn camera(p: Point3, forward: Vec3, beta: Rgb) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            p,
+            n: forward,
+            wo: forward,
+            mat: None,
+            object_id: 0,
+            front_face: true,
+            beta,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+            single: false,
+        }
+    }
+
+    fn light(p: Point3, n: Vec3, mat: Material, beta: Rgb, pdf: f64) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            p,
+            n,
+            wo: n,
+            mat: Some(mat),
+            object_id: 0,
+            front_face: true,
+            beta,
+            pdf_fwd: pdf,
+            pdf_rev: 0.0,
+            delta: false,
+            single: false,
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        matches!(self.kind, VertexKind::Surface | VertexKind::Light)
+    }
+
+    /// Cosine factor for light leaving or arriving in direction `w`.
+    fn cos(&self, w: Vec3) -> f64 {
+        if self.on_surface() {
+            dot(self.n, norm(w)).abs()
+        } else {
+            1.0
+        }
+    }
+
+    /// Turns a solid angle density at this vertex into an area density at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let dist_sqrd = w.len_sqrd();
+        if dist_sqrd == 0.0 {
+            return 0.0;
+        }
+
+        pdf * next.cos(w) / dist_sqrd
+    }
+
+    /// Scattering towards `next` for a path that arrived along `wo`.
+    fn f(&self, next: &Vertex, wavelengths: Option<SampledWavelengths>) -> Rgb {
+        match self.mat {
+            Some(mat) => mat.eval(self.wo, norm(next.p - self.p), self.n, wavelengths),
+            None => Rgb::BLACK,
+        }
+    }
+
+    /// Radiance emitted towards `next`.
+    fn le(&self, next: &Vertex, wavelengths: Option<SampledWavelengths>) -> Rgb {
+        let Some(mat) = self.mat else {
+            return Rgb::BLACK;
+        };
+
+        let w = next.p - self.p;
+        if self.on_surface() && (!self.front_face || dot(self.n, w) <= 0.0) {
+            return Rgb::BLACK;
+        }
+
+        mat.emitted(&Ray::new(self.p, w).with_wavelengths(wavelengths))
+    }
+
+    /// Area density at `next` of light leaving this (emissive) vertex towards it.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let pdf_dir = dot(self.n, norm(w)).max(0.0) / std::f64::consts::PI;
+        self.convert_density(pdf_dir, next)
+    }
+
+    /// Area density of a light path starting at this vertex.
+    fn pdf_light_origin(&self, world: &dyn Hittable) -> f64 {
+        world.emitter_pdf(self.object_id, self.p)
+    }
+
+    /// Area density at `next` of scattering towards it after arriving from `prev`.
+    fn pdf(&self, viewport: &Viewport, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let pdf_dir = match self.kind {
+            VertexKind::Light => return self.pdf_light(next),
+            VertexKind::Camera => viewport.pdf(self.p, next.p - self.p),
+            VertexKind::Surface | VertexKind::Medium => {
+                let (Some(mat), Some(prev)) = (self.mat, prev) else {
+                    return 0.0;
+                };
+                mat.pdf(norm(prev.p - self.p), norm(next.p - self.p), self.n)
+            }
+        };
+
+        self.convert_density(pdf_dir, next)
+    }
+}
+
+/// Transmittance along the segment between two vertices.
+fn visibility(world: &dyn Hittable, a: &Vertex, b: &Vertex) -> f64 {
+    let ray = Ray::new(a.p, b.p - a.p);
+    world.transmittance(&ray, 0.0001..0.9999)
+}
+
+fn remap0(pdf: f64) -> f64 {
+    if pdf == 0.0 { 1.0 } else { pdf }
+}
+
+impl Bdpt {
+    /// Extends `path` by following `ray`, until it leaves the scene, is
+    /// absorbed or has `max_vertices` vertices. Returns the throughput of the
+    /// final ray if it escaped.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        mut ray: Ray,
+        world: &dyn Hittable,
+        mut rng: &mut dyn RngCore,
+        mut beta: Rgb,
+        mut pdf_dir: f64,
+        max_vertices: usize,
+        path: &mut Vec<Vertex>,
+    ) -> Option<(Rgb, Ray)> {
+        while path.len() < max_vertices {
+            let Some(h) = world.hit(&ray, 0.00001..f64::INFINITY) else {
+                return Some((beta, ray));
+            };
+
+            let kind = match h.mat {
+                Material::Isotropic { .. } => VertexKind::Medium,
+                _ => VertexKind::Surface,
+            };
+            let mut vertex = Vertex {
+                kind,
+                p: h.p,
+                n: h.n,
+                wo: -norm(*ray.direction()),
+                mat: Some(h.mat),
+                object_id: h.object_id,
+                front_face: h.front_face,
+                beta,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+                delta: false,
+                single: ray.wavelengths().is_some_and(|wl| wl.is_single()),
+            };
+            let prev = path
+                .last()
+                .expect("paths start at a camera or light vertex");
+            vertex.pdf_fwd = prev.convert_density(pdf_dir, &vertex);
+            path.push(vertex);
+
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let Some((attenuation, new_ray)) = h.mat.scatter(&ray, &h, &mut rng) else {
+                break;
+            };
+
+            let n = path.len();
+            let wo = path[n - 1].wo;
+            let wi = norm(*new_ray.direction());
+            let pdf_rev_dir = if h.mat.is_specular() {
+                path[n - 1].delta = true;
+                pdf_dir = 0.0;
+                0.0
+            } else {
+                pdf_dir = h.mat.pdf(wo, wi, h.n);
+                h.mat.pdf(wi, wo, h.n)
+            };
+
+            path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev_dir, &path[n - 2]);
+            beta = beta * attenuation;
+            ray = new_ray;
+        }
+
+        None
+    }
+
+    /// Camera path starting with the camera vertex. Returns radiance from rays
+    /// that escaped to the background.
+    fn camera_path(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        viewport: &Viewport,
+        rng: &mut dyn RngCore,
+    ) -> (Vec<Vertex>, Rgb) {
+        let max_vertices = self.max_depth.max(0) as usize + 2;
+        let mut path = Vec::with_capacity(max_vertices);
+
+        let pdf_dir = viewport.pdf(*ray.origin(), *ray.direction());
+        path.push(Vertex::camera(
+            *ray.origin(),
+            viewport.forward(),
+            Rgb::new(1.0, 1.0, 1.0),
+        ));
+
+        let escaped = self.random_walk(
+            ray,
+            world,
+            rng,
+            Rgb::new(1.0, 1.0, 1.0),
+            pdf_dir,
+            max_vertices,
+            &mut path,
+        );
+
+        let background_radiance = match escaped {
+            Some((beta, ray)) => beta * background.color(&ray),
+            None => Rgb::BLACK,
+        };
+
+        (path, background_radiance)
+    }
+
+    /// Light path starting at a point on an area light, if there are any.
+    fn light_path(
+        &self,
+        world: &dyn Hittable,
+        wavelengths: Option<SampledWavelengths>,
+        mut rng: &mut dyn RngCore,
+    ) -> Vec<Vertex> {
+        let max_vertices = self.max_depth.max(0) as usize + 1;
+        let mut path = Vec::with_capacity(max_vertices);
+
+        let Some(sample) = world.sample_emitter(rng) else {
+            return path;
+        };
+
+        // Cosine-weighted emission from the outside of the light
+        let mut dir = sample.n + rand_unit_vec(&mut rng);
+        if dir.near_zero() {
+            dir = sample.n;
+        }
+        let dir = norm(dir);
+        let pdf_dir = dot(sample.n, dir) / std::f64::consts::PI;
+
+        let ray = Ray::new(sample.p, dir).with_wavelengths(wavelengths);
+        let le = sample.mat.emitted(&ray);
+
+        path.push(Vertex::light(
+            sample.p,
+            sample.n,
+            sample.mat,
+            le / sample.pdf,
+            sample.pdf,
+        ));
+
+        if pdf_dir > 0.0 {
+            let beta = le * (dot(sample.n, dir) / (sample.pdf * pdf_dir));
+            self.random_walk(ray, world, rng, beta, pdf_dir, max_vertices, &mut path);
+        }
+
+        path
+    }
+
+    /// Unweighted contribution of the strategy using `s` light and `t` camera
+    /// vertices, the pixel it lands on for `t == 1`, and the vertex sampled
+    /// to replace the path's endpoint for `s == 1` and `t == 1`.
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        &self,
+        world: &dyn Hittable,
+        viewport: &Viewport,
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        wavelengths: Option<SampledWavelengths>,
+        mut rng: &mut dyn RngCore,
+    ) -> Option<(Rgb, Option<usize>, Option<Vertex>)> {
+        if s == 0 {
+            // The camera path hit a light by itself
+            let pt = &camera[t - 1];
+            let l = pt.beta * pt.le(&camera[t - 2], wavelengths);
+            return Some((l, None, None));
+        }
+
+        if t == 1 {
+            // Connect the light path to a point on the lens
+            let qs = &light[s - 1];
+            if qs.delta {
+                return None;
+            }
+
+            let origin = viewport.sample_lens(&mut rng);
+            let pixel = viewport.pixel_at(origin, qs.p - origin)?;
+            let importance = viewport.importance(origin, qs.p);
+            let sampled = Vertex::camera(origin, viewport.forward(), Rgb::new(1.0, 1.0, 1.0));
+
+            let mut l =
+                qs.beta * qs.f(&sampled, wavelengths) * (importance * qs.cos(origin - qs.p));
+            if l != Rgb::BLACK {
+                l = l * visibility(world, qs, &sampled);
+            }
+            return Some((l, Some(pixel), Some(sampled)));
+        }
+
+        let pt = &camera[t - 1];
+        if pt.delta {
+            return None;
+        }
+
+        if s == 1 {
+            // Pick a new point on a light, as in next event estimation
+            let sample = world.sample_emitter(rng)?;
+            let w = sample.p - pt.p;
+            let dist_sqrd = w.len_sqrd();
+            let cos_light = -dot(sample.n, w) / dist_sqrd.sqrt();
+            if cos_light <= 0.0 {
+                return None;
+            }
+
+            let ray = Ray::new(sample.p, -w).with_wavelengths(wavelengths);
+            let le = sample.mat.emitted(&ray);
+            let pdf = sample.pdf * dist_sqrd / cos_light;
+            let sampled = Vertex::light(sample.p, sample.n, sample.mat, le / pdf, sample.pdf);
+
+            let mut l = pt.beta * pt.f(&sampled, wavelengths) * sampled.beta * pt.cos(w);
+            if l != Rgb::BLACK {
+                l = l * visibility(world, pt, &sampled);
+            }
+            return Some((l, None, Some(sampled)));
+        }
+
+        let qs = &light[s - 1];
+        if qs.delta {
+            return None;
+        }
+
+        let w = pt.p - qs.p;
+        let g = qs.cos(w) * pt.cos(w) / w.len_sqrd();
+        let mut l = qs.beta * qs.f(pt, wavelengths) * pt.f(qs, wavelengths) * pt.beta * g;
+
+        // Both halves dropped the secondary wavelengths and made up for them.
+        if qs.single && pt.single {
+            l = l / 3.0;
+        }
+        if l != Rgb::BLACK {
+            l = l * visibility(world, qs, pt);
+        }
+        Some((l, None, None))
+    }
+
+    /// Balance heuristic weight of the strategy using `s` light and `t` camera
+    /// vertices, with `sampled` replacing the endpoint for `s == 1` or `t == 1`.
+    #[allow(clippy::too_many_arguments)]
+    fn mis_weight(
+        &self,
+        world: &dyn Hittable,
+        viewport: &Viewport,
+        light: &[Vertex],
+        camera: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        let pt = match (t, sampled) {
+            (1, Some(v)) => v,
+            _ => &camera[t - 1],
+        };
+        let qs = match (s, sampled) {
+            (0, _) => None,
+            (1, Some(v)) => Some(v),
+            _ => Some(&light[s - 1]),
+        };
+        let pt_minus = (t >= 2).then(|| &camera[t - 2]);
+        let qs_minus = (s >= 2).then(|| &light[s - 2]);
+
+        // Paths ending on lights that can't be sampled are only found this way.
+        if s == 0 && pt.pdf_light_origin(world) == 0.0 {
+            return 1.0;
+        }
+
+        // (pdf_rev, pdf_fwd, delta) of every vertex with this strategy's
+        // connection in place
+        let mut camera_pdfs: Vec<(f64, f64, bool)> = camera[..t]
+            .iter()
+            .map(|v| (v.pdf_rev, v.pdf_fwd, v.delta))
+            .collect();
+        let mut light_pdfs: Vec<(f64, f64, bool)> = light[..s]
+            .iter()
+            .map(|v| (v.pdf_rev, v.pdf_fwd, v.delta))
+            .collect();
+
+        camera_pdfs[t - 1] = (
+            match qs {
+                Some(qs) => qs.pdf(viewport, qs_minus, pt),
+                None => pt.pdf_light_origin(world),
+            },
+            pt.pdf_fwd,
+            false,
+        );
+        if let Some(pt_minus) = pt_minus {
+            camera_pdfs[t - 2].0 = match qs {
+                Some(qs) => pt.pdf(viewport, Some(qs), pt_minus),
+                None => pt.pdf_light(pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light_pdfs[s - 1] = (pt.pdf(viewport, pt_minus, qs), qs.pdf_fwd, false);
+        }
+        if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+            light_pdfs[s - 2].0 = qs.pdf(viewport, Some(pt), qs_minus);
+        }
+
+        // Ratios of the other strategies' densities to this one's. Specular
+        // vertices have zero densities on both sides, which cancel.
+        let mut sum = 0.0;
+
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(camera_pdfs[i].0) / remap0(camera_pdfs[i].1);
+            if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+                sum += ri;
+            }
+        }
+
+        let mut ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(light_pdfs[i].0) / remap0(light_pdfs[i].1);
+            let prev_delta = i > 0 && light_pdfs[i - 1].2;
+            if !light_pdfs[i].2 && !prev_delta {
+                sum += ri;
+            }
+        }
+
+        1.0 / (1.0 + sum)
+    }
+
+    fn trace(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+        splats: &mut Splats,
+    ) -> Rgb {
+        let wavelengths = ray.wavelengths();
+        let viewport = splats.viewport;
+
+        let (camera, mut radiance) = self.camera_path(ray, world, background, viewport, rng);
+        let light = self.light_path(world, wavelengths, rng);
+
+        for t in 1..=camera.len() {
+            for s in 0..=light.len() {
+                let depth = (s + t) as i32 - 2;
+                if (s == 1 && t == 1) || depth < 0 || depth > self.max_depth {
+                    continue;
+                }
+                if s == 0 && t < 2 {
+                    continue;
+                }
+
+                let Some((l, pixel, sampled)) =
+                    self.connect(world, viewport, &light, &camera, s, t, wavelengths, rng)
+                else {
+                    continue;
+                };
+                if l == Rgb::BLACK {
+                    continue;
+                }
+
+                let weight =
+                    self.mis_weight(world, viewport, &light, &camera, sampled.as_ref(), s, t);
+                match pixel {
+                    Some(pixel) => splats.samples.push((pixel, l * weight)),
+                    None => radiance = radiance + l * weight,
+                }
+            }
+        }
+
+        radiance
+    }
+}
+
+impl Integrator for Bdpt {
+    /// Connecting paths and weighting them needs the camera's viewport, so
+    /// without it this is the estimate of the camera path on its own: the
+    /// light it finds, like a path tracer that doesn't sample lights. The
+    /// camera renders through `radiance_with_splats`.
+    fn radiance(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+    ) -> Rgb {
+        let wavelengths = ray.wavelengths();
+        let max_vertices = self.max_depth.max(0) as usize + 2;
+        let mut path = vec![Vertex::camera(
+            *ray.origin(),
+            norm(*ray.direction()),
+            Rgb::new(1.0, 1.0, 1.0),
+        )];
+
+        let one = Rgb::new(1.0, 1.0, 1.0);
+        let escaped = self.random_walk(ray, world, rng, one, 0.0, max_vertices, &mut path);
+        let mut radiance = match escaped {
+            Some((beta, ray)) => beta * background.color(&ray),
+            None => Rgb::BLACK,
+        };
+        for pair in path.windows(2) {
+            radiance = radiance + pair[1].beta * pair[1].le(&pair[0], wavelengths);
+        }
+
+        radiance
+    }
+
+    fn radiance_with_splats(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+        aovs: Option<&mut Aovs>,
+        splats: &mut Splats,
+    ) -> Rgb {
+        if let Some(aovs) = aovs {
+            aovs.record_surface(&ray, world.hit(&ray, 0.00001..f64::INFINITY).as_ref());
+        }
+
+        self.trace(ray, world, background, rng, splats)
+    }
+}
@@ -1,22 +1,490 @@
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::ensure;
+use clap::ValueEnum;
+
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::Rng;
-use rayon::iter::IntoParallelRefMutIterator;
+use rand::{Rng, random};
+use rayon::{
+    current_num_threads,
+    iter::{IntoParallelRefIterator, ParallelIterator},
+};
 
 use crate::{
+    adaptive::{AdaptiveSampling, PixelStats},
+    ambient_occlusion::AmbientOcclusion,
+    cancel::CancelToken,
+    checkpoint::{CheckpointFile, Checkpointed, Checkpointing},
+    color_space::ColorSpace,
+    crop::CropWindow,
+    filter::{Filter, FilterKind},
     hittable::Hittable,
     image::Image,
+    integrator::{Aovs, Background, Integrator, LightId, PathTracer, Splats},
     point::Point3,
     ray::Ray,
     rbg::Rgb,
-    vec3::{Vec3, cross, lerp, norm, rand_in_unit_disk},
+    sampler::{Sampler, SamplerKind, hash},
+    spectrum::SampledWavelengths,
+    tile::{Tile, TileOrder, tiles},
+    vec3::{Vec3, cross, dot, norm, rand_in_unit_disk},
 };
-use rayon::iter::IndexedParallelIterator;
-use rayon::iter::ParallelIterator;
+
+/// AOVs and sample statistics summed over the samples a pixel has taken so
+/// far. Its colour is accumulated on the filtered film instead.
+struct PixelState {
+    aovs: Option<Aovs>,
+    stats: PixelStats,
+}
+
+impl PixelState {
+    fn new(aovs: bool) -> Self {
+        Self {
+            aovs: aovs.then(Aovs::new),
+            stats: PixelStats::default(),
+        }
+    }
+
+    fn merge(&mut self, other: PixelState) {
+        if let (Some(aovs), Some(other)) = (self.aovs.as_mut(), &other.aovs) {
+            aovs.accumulate(other, |film| film);
+        }
+        self.stats.merge(&other.stats);
+    }
+}
+
+impl Checkpointed for PixelState {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.aovs.save(out);
+        self.stats.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            aovs: Option::load(input)?,
+            stats: PixelStats::load(input)?,
+        })
+    }
+}
+
+/// Sample sums of a render in progress, in film values (XYZ for spectral
+/// renders, linear sRGB otherwise).
+struct Film {
+    /// Filter weighted sums, see `Image::add_weighted`.
+    image: Image,
+    pixels: Vec<PixelState>,
+    /// Film values splatted onto each pixel, summed over all samples.
+    splats: Vec<Rgb>,
+    /// Samples taken over all pixels so far.
+    samples: u64,
+}
+
+impl Film {
+    fn new(width: usize, height: usize, aovs: bool) -> Self {
+        Self {
+            image: Image::new(width, height),
+            pixels: (0..width * height).map(|_| PixelState::new(aovs)).collect(),
+            splats: vec![Rgb::BLACK; width * height],
+            samples: 0,
+        }
+    }
+
+    /// Mean relative standard error of the luminance of the pixels in
+    /// `area`, see `PixelStats::relative_error`.
+    fn noise(&self, area: &Tile) -> f64 {
+        let sum: f64 = area
+            .pixels(self.image.width)
+            .map(|idx| self.pixels[idx].stats.relative_error())
+            .sum();
+        sum / (area.width * area.height) as f64
+    }
+
+    /// Progress so far of a render of `area` started at `start`, with every
+    /// pixel still sampling at `samples` samples.
+    fn stats(&self, samples: u32, start: Instant, area: &Tile) -> PassStats {
+        PassStats {
+            samples,
+            mean_samples: self.samples as f64 / (area.width * area.height) as f64,
+            noise: self.noise(area),
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Film value of pixel `idx` so far. Splats come from the samples of
+    /// every pixel, so they're averaged over the mean sample count. With
+    /// adaptive sampling, the MIS weights of splatting integrators assume
+    /// that count everywhere and are only approximate. Before the first
+    /// sample there are no splats to scale.
+    fn value(&self, idx: usize) -> Rgb {
+        let splat_scale = self.pixels.len() as f64 / self.samples.max(1) as f64;
+        self.image.weighted_average(idx) + self.splats[idx] * splat_scale
+    }
+}
+
+impl Checkpointed for Film {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.image.save(out);
+        self.pixels.save(out);
+        self.splats.save(out);
+        self.samples.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            image: Image::load(input)?,
+            pixels: Vec::load(input)?,
+            splats: Vec::load(input)?,
+            samples: u64::load(input)?,
+        })
+    }
+}
+
+/// Where a render is in its passes.
+struct Progress {
+    /// Samples every pixel that's still sampling has before the current
+    /// pass, and will have after it.
+    done: u32,
+    target: u32,
+    /// Pixels of each tile that are still sampling.
+    active: Vec<Vec<usize>>,
+    /// How many of the tiles with pixels still sampling the current pass has
+    /// rendered.
+    tiles_done: usize,
+}
+
+impl Checkpointed for Progress {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.done.save(out);
+        self.target.save(out);
+        self.active.save(out);
+        self.tiles_done.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            done: u32::load(input)?,
+            target: u32::load(input)?,
+            active: Vec::load(input)?,
+            tiles_done: usize::load(input)?,
+        })
+    }
+}
+
+/// Gets told about a render's progress by `Camera::render`.
+pub trait RenderObserver {
+    /// A tile finished its samples of a pass; `values` are its pixels' output
+    /// values so far, row by row. With adaptive sampling or passes, tiles
+    /// finish again in every pass they take samples in.
+    fn tile_done(&mut self, tile: &Tile, values: &[Rgb]) {
+        let _ = (tile, values);
+    }
+
+    /// A pass completed. `snapshot` resolves the image so far, with its
+    /// layers.
+    fn pass_done(&mut self, stats: &PassStats, snapshot: &dyn Fn() -> Image) {
+        let _ = (stats, snapshot);
+    }
+
+    /// The render was cancelled part way through a pass, instead of
+    /// completing it.
+    fn cancelled(&mut self, stats: &PassStats) {
+        let _ = stats;
+    }
+}
+
+/// Progress of a render after one of its passes.
+#[derive(Clone, Copy)]
+pub struct PassStats {
+    /// Samples every pixel that's still sampling has taken.
+    pub samples: u32,
+    /// Samples per pixel on average, fewer than `samples` once adaptive
+    /// sampling stopped some pixels.
+    pub mean_samples: f64,
+    /// Mean relative standard error of the pixels' luminance, infinite until
+    /// every pixel has 2 samples and for integrators rendering through
+    /// `Integrator::render_film`.
+    pub noise: f64,
+    pub elapsed: Duration,
+}
+
+/// Filter weighted film values and weights of the pixels within
+/// `Camera::filter_reach` of a pixel, row by row.
+type Footprint = Vec<(Rgb, f64)>;
+
+/// Number of bytes `value` saves to.
+fn saved_len(value: &impl Checkpointed) -> u64 {
+    let mut bytes = Vec::new();
+    value.save(&mut bytes);
+    bytes.len() as u64
+}
+
+/// What `render_samples` takes for a pixel: its state, filtered film values
+/// and splats.
+type PixelSamples = (PixelState, Footprint, Vec<(usize, Rgb)>);
+
+/// How a distributed render is split into jobs.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum JobSplit {
+    /// Every sample of one tile per job
+    Tiles,
+    /// A range of samples of every pixel per job
+    Samples,
+}
+
+/// Part of a render a worker takes: `samples` of the pixels in `area`.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub area: Tile,
+    pub samples: Range<u32>,
+}
+
+impl Checkpointed for Job {
+    fn save(&self, out: &mut Vec<u8>) {
+        (self.area.x, self.area.y).save(out);
+        (self.area.width, self.area.height).save(out);
+        (self.samples.start, self.samples.end).save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        let (x, y) = <(usize, usize)>::load(input)?;
+        let (width, height) = <(usize, usize)>::load(input)?;
+        let (start, end) = <(u32, u32)>::load(input)?;
+        Ok(Self {
+            area: Tile {
+                x,
+                y,
+                width,
+                height,
+            },
+            samples: start..end,
+        })
+    }
+}
+
+/// Samples a worker took for a job, for each pixel of its area row by row.
+pub struct JobResult(Vec<PixelSamples>);
+
+impl Checkpointed for JobResult {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self(Vec::load(input)?))
+    }
+}
+
+/// Adds the results of a distributed render's jobs to its film in job
+/// order, whatever order they arrive in, so the image doesn't depend on
+/// which worker rendered what. Observers hear of every job added as a tile,
+/// and of every run of jobs with the same samples as a pass.
+pub struct JobMerger<'a> {
+    camera: &'a Camera,
+    jobs: &'a [Job],
+    window: Tile,
+    /// Pixels the jobs sample, see `Camera::render`.
+    sampled: Tile,
+    film: Film,
+    /// Jobs before this one are on the film.
+    next: usize,
+    pending: BTreeMap<usize, JobResult>,
+    /// Samples every pixel has after the last finished pass.
+    passed: u32,
+    start: Instant,
+}
+
+impl JobMerger<'_> {
+    /// Adds the result of job `index`, unless it isn't what the job asked
+    /// for.
+    pub fn add(
+        &mut self,
+        index: usize,
+        result: JobResult,
+        observer: &mut impl RenderObserver,
+    ) -> anyhow::Result<()> {
+        self.check(&self.jobs[index], &result)?;
+        self.pending.insert(index, result);
+        while let Some(result) = self.pending.remove(&self.next) {
+            self.merge(self.next, result, observer);
+            self.next += 1;
+
+            let samples = &self.jobs[self.next - 1].samples;
+            if self
+                .jobs
+                .get(self.next)
+                .is_none_or(|job| job.samples != *samples)
+            {
+                self.passed = samples.end;
+                let stats = self.film.stats(self.passed, self.start, &self.sampled);
+                observer.pass_done(&stats, &|| self.camera.resolve(&self.film, &self.window));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks `result` has the pixels and samples `job` asked for, so a
+    /// broken worker can't corrupt the film.
+    fn check(&self, job: &Job, result: &JobResult) -> anyhow::Result<()> {
+        let pixels = job.area.width * job.area.height;
+        ensure!(
+            result.0.len() == pixels,
+            "{} pixels instead of {pixels}",
+            result.0.len()
+        );
+
+        let side = 2 * self.camera.filter_reach() + 1;
+        for (state, filtered, splats) in &result.0 {
+            ensure!(
+                state.stats.count as usize == job.samples.len(),
+                "a pixel has {} samples instead of {}",
+                state.stats.count,
+                job.samples.len()
+            );
+            ensure!(
+                state.aovs.is_some() == self.camera.aovs,
+                "a pixel's AOVs don't match the render"
+            );
+            ensure!(
+                filtered.len() == side * side,
+                "a pixel's filter footprint has the wrong size"
+            );
+            ensure!(
+                splats
+                    .iter()
+                    .all(|(pixel, _)| *pixel < self.film.splats.len()),
+                "a splat lands outside the image"
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether every job's result is on the film.
+    pub fn done(&self) -> bool {
+        self.next == self.jobs.len()
+    }
+
+    /// The image of the jobs added so far, all of them unless the render
+    /// was cancelled.
+    pub fn finish(mut self, observer: &mut impl RenderObserver) -> Image {
+        if !self.done() {
+            for (index, result) in std::mem::take(&mut self.pending) {
+                self.merge(index, result, observer);
+            }
+            observer.cancelled(&self.film.stats(self.passed, self.start, &self.sampled));
+        }
+        self.camera.resolve(&self.film, &self.window)
+    }
+
+    fn merge(&mut self, index: usize, result: JobResult, observer: &mut impl RenderObserver) {
+        let area = &self.jobs[index].area;
+        for (idx, samples) in area.pixels(self.film.image.width).zip(result.0) {
+            self.film.samples += samples.0.stats.count as u64;
+            self.camera.add_samples(&mut self.film, idx, samples);
+        }
+
+        let values: Vec<Rgb> = area
+            .pixels(self.film.image.width)
+            .map(|idx| self.camera.to_output(self.film.value(idx)))
+            .collect();
+        observer.tile_done(area, &values);
+    }
+}
+
+/// Hands the progress of an integrator rendering through
+/// `Integrator::render_film` to the render's observer, as a tile covering the
+/// whole image and then a pass.
+pub struct FilmProgress<'a> {
+    camera: &'a Camera,
+    observer: &'a mut dyn RenderObserver,
+    window: Tile,
+    start: Instant,
+}
+
+impl FilmProgress<'_> {
+    /// The render so far has taken `mean_samples` samples per pixel on
+    /// average, and `film` holds every pixel's film value.
+    pub fn pass_done(&mut self, mean_samples: f64, film: &[Rgb]) {
+        let view = self.camera.viewport();
+        let whole = Tile {
+            x: 0,
+            y: 0,
+            width: view.width(),
+            height: view.height(),
+        };
+        let values: Vec<Rgb> = film
+            .iter()
+            .map(|&value| self.camera.to_output(value))
+            .collect();
+        self.observer.tile_done(&whole, &values);
+
+        let stats = PassStats {
+            samples: mean_samples as u32,
+            mean_samples,
+            // The film alone doesn't tell how noisy it is
+            noise: f64::INFINITY,
+            elapsed: self.start.elapsed(),
+        };
+        self.observer.pass_done(&stats, &|| {
+            let mut img = Image::new(whole.width, whole.height);
+            img.as_mut_slice().copy_from_slice(&values);
+            self.camera.crop_output(img, &self.window)
+        });
+    }
+}
+
+/// Tiles rendered in parallel per thread before their results are added to
+/// the film.
+const TILES_PER_THREAD: usize = 4;
 
 pub struct Camera {
     pub aspect_ratio: f64,
     pub image_width: usize,
     pub samples_per_pixel: u32,
+    /// Stop sampling converged pixels early, with `samples_per_pixel` as the
+    /// most a pixel takes.
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Pattern of the random numbers the samples of a pixel draw.
+    pub sampler: SamplerKind,
+    /// Every random number of a render derives from this, so renders with
+    /// the same seed and settings are bit-identical.
+    pub seed: u64,
+    /// Render in passes adding this many samples to every pixel, so the image
+    /// so far can be looked at between them.
+    pub pass_samples: Option<u32>,
+    /// Stop taking passes once the next one wouldn't finish within this
+    /// long of the start. Passes are sized from the time the ones before
+    /// took.
+    pub time_budget: Option<Duration>,
+    /// Stop taking passes once the image's noise (see `PassStats::noise`) is
+    /// this low.
+    pub target_noise: Option<f64>,
+    /// Save the render's progress every now and then, or resume from it.
+    /// Only integrators rendering in passes are checkpointed.
+    pub checkpoint: Option<Checkpointing>,
+    /// Only render this part of the image. Integrators rendering through
+    /// `render_film` render the whole image and crop it afterwards.
+    pub crop: Option<CropWindow>,
+    /// With a crop window, return the full-size image with the rest black
+    /// instead of just the window.
+    pub crop_full_frame: bool,
+    /// Stops the render once cancelled. Pixels keep the samples they took,
+    /// so `render` still returns the image so far, correctly normalized.
+    pub cancel: CancelToken,
+    /// Width and height of the square tiles the image is rendered in.
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// Reconstruction filter weighting samples into the pixels around them.
+    /// Integrators rendering through `render_film` and the light splats of
+    /// BDPT are box filtered.
+    pub filter: Filter,
+    pub integrator: Box<dyn Integrator>,
+    /// Bounces the integrator follows at most, which bounds how many splats
+    /// and lights a sample can record.
     pub max_depth: i32,
     pub vfov: f64,
     pub look_from: Point3,
@@ -24,26 +492,15 @@ pub struct Camera {
     pub vup: Vec3,
     pub defocus_angle: f64,
     pub focus_dist: f64,
-}
-
-fn ray_color(ray: &Ray, world: &impl Hittable, rng: &mut impl Rng, max_depth: i32) -> Rgb {
-    if max_depth <= 0 {
-        return Rgb::BLACK;
-    }
-    match world.hit(ray, 0.00001..f64::INFINITY) {
-        Some(h) => match h.mat.scatter(ray, &h, rng) {
-            Some((attenuation, new_ray)) => {
-                attenuation * ray_color(&new_ray, world, rng, max_depth - 1)
-            }
-            None => Rgb::BLACK,
-        },
-        None => {
-            let u = norm(*ray.direction());
-            let blend = 0.9 * (u.y + 1.0);
-
-            lerp(&Rgb::new(1.0, 1.0, 1.0), &Rgb::new(0.5, 0.7, 1.0), blend)
-        }
-    }
+    /// Trace spectral paths and accumulate CIE XYZ instead of RGB.
+    pub spectral: bool,
+    pub color_space: ColorSpace,
+    pub background: Background,
+    /// Record auxiliary outputs (albedo, normal, depth, light path and per-light
+    /// contributions, luminance variance) as extra image layers.
+    pub aovs: bool,
+    /// Settings for the ambient occlusion layer written with the AOVs.
+    pub occlusion: AmbientOcclusion,
 }
 
 impl Camera {
@@ -52,6 +509,20 @@ impl Camera {
             aspect_ratio,
             image_width,
             samples_per_pixel: spp,
+            adaptive: None,
+            sampler: SamplerKind::Sobol,
+            seed: random(),
+            pass_samples: None,
+            time_budget: None,
+            target_noise: None,
+            checkpoint: None,
+            crop: None,
+            crop_full_frame: false,
+            cancel: CancelToken::new(),
+            tile_size: 16,
+            tile_order: TileOrder::Spiral,
+            filter: Filter::new(FilterKind::Box),
+            integrator: Box::new(PathTracer::new(max_depth)),
             max_depth,
             vfov: 45.0,
             look_from: Point3::new(0.0, 0.0, 0.0),
@@ -59,17 +530,21 @@ impl Camera {
             vup: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            spectral: false,
+            color_space: ColorSpace::Srgb,
+            background: Background::Sky,
+            aovs: false,
+            occlusion: AmbientOcclusion::new(1, 2.0),
         }
     }
 
-    pub fn render(&self, world: &impl Hittable) -> Image {
+    pub fn viewport(&self) -> Viewport {
         // Image dimensions
         let image_width = self.image_width;
         let aspect_ratio = self.aspect_ratio;
         let image_height = ((image_width as f64) / aspect_ratio) as usize;
 
         let camera_center = self.look_from;
-        let pixels_samples_scale: f64 = 1.0 / (self.samples_per_pixel as f64);
 
         // Viewport variables
         let theta = self.vfov.to_radians();
@@ -93,59 +568,907 @@ impl Camera {
         let pixel_origin = viewport_upper_left + pixel_delta_u / 2.0 + pixel_delta_v / 2.0;
 
         let defocus_radius = self.focus_dist * (self.defocus_angle / 2.0).to_radians().tan();
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
 
-        let mut img = Image::new(image_width, image_height);
+        Viewport {
+            width: image_width,
+            height: image_height,
+            center: camera_center,
+            pixel_origin,
+            pixel_delta_u,
+            pixel_delta_v,
+            defocus_disk_u: u * defocus_radius,
+            defocus_disk_v: v * defocus_radius,
+            forward: -w,
+            focus_dist: self.focus_dist,
+            image_area: viewport_width * viewport_height,
+        }
+    }
 
-        let bar =
-            ProgressBar::new((img.width * img.height * (self.samples_per_pixel as usize)) as u64);
+    /// A random camera ray through pixel `(i, j)`, carrying `wavelengths`.
+    pub fn camera_ray(
+        &self,
+        view: &Viewport,
+        i: usize,
+        j: usize,
+        wavelengths: Option<SampledWavelengths>,
+        rng: &mut impl Rng,
+    ) -> Ray {
+        let x = i as f64 + rng.random::<f64>();
+        let y = j as f64 + rng.random::<f64>();
+        self.ray_through(view, x, y, wavelengths, rng)
+    }
 
-        bar.set_style(
-            ProgressStyle::with_template( "{spinner:.green} [{elapsed_precise}/{eta_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {percent}% {per_sec}")
-                .unwrap(),
-        );
-        let bar_clone = bar.clone();
+    /// A camera ray through film position `(x, y)`, in pixels. Pixel `(i, j)`
+    /// covers `[i, i + 1) x [j, j + 1)`.
+    fn ray_through(
+        &self,
+        view: &Viewport,
+        x: f64,
+        y: f64,
+        wavelengths: Option<SampledWavelengths>,
+        rng: &mut impl Rng,
+    ) -> Ray {
+        let pixel_sample = view.pixel_origin + (x * view.pixel_delta_u) + (y * view.pixel_delta_v);
+
+        let ray_origin = if self.defocus_angle <= 0.0 {
+            view.center
+        } else {
+            view.sample_lens(rng)
+        };
+
+        let ray_dir = pixel_sample - ray_origin;
+        Ray::new(ray_origin, ray_dir).with_wavelengths(wavelengths)
+    }
+
+    /// Pixels around a sample's pixel that its filter can reach, along each axis.
+    fn filter_reach(&self) -> usize {
+        // Pixel centres are within `radius` of samples in the pixel
+        // `radius + 0.5` or less away.
+        ((self.filter.radius + 0.5).ceil() as usize).saturating_sub(1)
+    }
+
+    /// Renders samples `samples` of pixel `idx`, returning their AOVs, their
+    /// filtered film values (XYZ for spectral renders, linear sRGB otherwise)
+    /// including pixels outside the image, and the film values they splatted
+    /// onto other pixels.
+    fn render_samples(
+        &self,
+        view: &Viewport,
+        world: &dyn Hittable,
+        idx: usize,
+        samples: Range<u32>,
+    ) -> PixelSamples {
+        let (i, j) = (idx % view.width, idx / view.width);
+        let reach = self.filter_reach() as isize;
+        let side = 2 * reach as usize + 1;
+        let mut filtered = vec![(Rgb::BLACK, 0.0); side * side];
+        let mut sampler = Sampler::new(self.sampler, self.samples_per_pixel, self.seed);
+        let mut state = PixelState::new(self.aovs);
+        let mut splats = Splats {
+            viewport: view,
+            samples: Vec::new(),
+        };
+
+        for sp in samples {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            sampler.start_pixel_sample(i, j, sp);
+            let splat_start = splats.samples.len();
+
+            // Pixel position comes first so it gets the best stratified dimensions
+            let (u, v): (f64, f64) = (sampler.random(), sampler.random());
+            let ray = self.ray_through(view, i as f64 + u, j as f64 + v, None, &mut sampler);
+            let wavelengths = self
+                .spectral
+                .then(|| SampledWavelengths::sample_visible(sampler.next_f64()));
+            sampler.align_to_pair();
+            let ray = ray.with_wavelengths(wavelengths);
+            let to_film = |radiance: Rgb| to_film(radiance, wavelengths);
+
+            let mut sample_aovs = state.aovs.is_some().then(Aovs::new);
+            if let Some(sample_aovs) = sample_aovs.as_mut() {
+                sample_aovs.occlusion = self.occlusion.visibility(&ray, world, &mut sampler);
+            }
+
+            let radiance = self.integrator.radiance_with_splats(
+                ray,
+                world,
+                &self.background,
+                &mut sampler,
+                sample_aovs.as_mut(),
+                &mut splats,
+            );
+
+            if let (Some(pixel_aovs), Some(sample_aovs)) = (state.aovs.as_mut(), &sample_aovs) {
+                pixel_aovs.accumulate(sample_aovs, to_film);
+            }
+            for (_, splat) in splats.samples[splat_start..].iter_mut() {
+                *splat = to_film(*splat);
+            }
+
+            let film = to_film(radiance);
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    // Offset from the centre of the pixel `(dx, dy)` away
+                    let weight = self.filter.eval(u - 0.5 - dx as f64, v - 0.5 - dy as f64);
+                    let (sum, weights) =
+                        &mut filtered[(dy + reach) as usize * side + (dx + reach) as usize];
+                    *sum = *sum + film * weight;
+                    *weights += weight;
+                }
+            }
+            state.stats.add(film_luminance(film, self.spectral));
+        }
+
+        (state, filtered, splats.samples)
+    }
+
+    /// Renders the image, telling `observer` about tiles and passes as they
+    /// complete.
+    pub fn render(
+        &self,
+        world: &impl Hittable,
+        observer: &mut impl RenderObserver,
+    ) -> anyhow::Result<Image> {
+        let view = self.viewport();
+        let window = self.crop_pixels()?;
+
+        let mut progress = FilmProgress {
+            camera: self,
+            observer: &mut *observer,
+            window,
+            start: Instant::now(),
+        };
+        if let Some(values) = self.integrator.render_film(self, world, &mut progress) {
+            let mut img = Image::new(view.width, view.height);
+            for (pixel, value) in img.as_mut_slice().iter_mut().zip(values) {
+                *pixel = self.to_output(value);
+            }
+            return Ok(self.crop_output(img, &window));
+        }
 
-        let pixel_slice = img.as_mut_slice();
+        let mut start = Instant::now();
+        let mut last_checkpoint = start;
+        // Samples reach pixels `filter_reach` away, so the pixels around the
+        // window are sampled too, for its edges to come out as in a render
+        // of the whole image
+        let sampled = window.grow(self.filter_reach(), view.width, view.height);
+        let pixel_count = sampled.width * sampled.height;
+        let tiles = tiles(&sampled, self.tile_size, self.tile_order);
 
-        pixel_slice
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(idx, pixel_ref)| {
-                let i = idx % image_width;
-                let j = idx / image_width;
+        // Without adaptive sampling, passes, a budget or a target noise a
+        // single pass renders every sample. Otherwise every pixel that isn't
+        // converged yet takes `pass_samples` more samples each pass, or
+        // doubles its sample count.
+        let open_ended = self.time_budget.is_some() || self.target_noise.is_some();
+        let (mut film, mut progress) = match &self.checkpoint {
+            Some(checkpoint) if checkpoint.resume => {
+                let (film, progress, elapsed) =
+                    self.load_checkpoint(&checkpoint.path, &view, &window)?;
+                ensure!(
+                    progress.active.len() == tiles.len(),
+                    "the checkpoint's tiles don't match the image"
+                );
+                // Count the time the render took before, for the budget
+                start = start.checked_sub(elapsed).unwrap_or(start);
+                (film, progress)
+            }
+            _ => {
+                let film = Film::new(view.width, view.height, self.aovs);
+                let target = match (&self.adaptive, self.pass_samples) {
+                    (Some(adaptive), _) => adaptive.min_samples,
+                    (None, Some(pass_samples)) => pass_samples,
+                    (None, None) if open_ended => 1,
+                    (None, None) => self.samples_per_pixel,
+                };
+                let progress = Progress {
+                    done: 0,
+                    target: target.max(1).min(self.samples_per_pixel),
+                    active: tiles
+                        .iter()
+                        .map(|tile| tile.pixels(view.width).collect())
+                        .collect(),
+                    tiles_done: 0,
+                };
+                (film, progress)
+            }
+        };
 
-                let mut thread_rng = rand::rng();
+        // Renders with a budget or target noise don't know how long they'll take
+        let bar = progress_bar(if open_ended {
+            film.samples
+        } else {
+            pixel_count as u64 * self.samples_per_pixel as u64
+        });
+        bar.set_position(film.samples);
 
-                let mut pixel_color = Rgb::new(0.0, 0.0, 0.0);
+        loop {
+            let (done, target) = (progress.done, progress.target);
+            let pending: Vec<usize> = (0..tiles.len())
+                .filter(|&t| !progress.active[t].is_empty())
+                .collect();
+            if open_ended {
+                let sampling: usize = pending[progress.tiles_done..]
+                    .iter()
+                    .map(|&t| progress.active[t].len())
+                    .sum();
+                bar.inc_length(sampling as u64 * (target - done) as u64);
+            }
 
-                for _sp in 0..self.samples_per_pixel {
-                    let x_dither: f64 = thread_rng.random::<f64>();
-                    let y_dither: f64 = thread_rng.random::<f64>();
+            // Each thread renders a tile at a time, see `batches`. A resumed
+            // render picks up at a batch boundary, so where it was stopped
+            // doesn't change the sums either.
+            let batch_size = TILES_PER_THREAD * current_num_threads();
+            for batch in batches(progress.tiles_done..pending.len(), batch_size) {
+                let batch_end = batch.end;
+                let batch = &pending[batch];
+                let results: Vec<Vec<_>> = batch
+                    .par_iter()
+                    .map(|&t| {
+                        let results = progress.active[t]
+                            .iter()
+                            .map(|&idx| self.render_samples(&view, world, idx, done..target))
+                            .collect();
+                        bar.inc(progress.active[t].len() as u64 * (target - done) as u64);
+                        results
+                    })
+                    .collect();
+
+                // Once the render is cancelled the workers stop sampling, so
+                // the batch may be part way done. It's checkpointed without
+                // the batch, which resuming then renders again in full.
+                let cancelled = self.cancel.is_cancelled();
+                if cancelled && let Some(checkpoint) = &self.checkpoint {
+                    self.save_checkpoint(checkpoint, &view, &window, &film, &progress, start)?;
+                }
 
-                    let pixel_sample = pixel_origin
-                        + (((i as f64) + x_dither) * pixel_delta_u)
-                        + (((j as f64) + y_dither) * pixel_delta_v);
+                for (&t, results) in batch.iter().zip(results) {
+                    for (&idx, result) in progress.active[t].iter().zip(results) {
+                        film.samples += result.0.stats.count as u64;
+                        self.add_samples(&mut film, idx, result);
+                    }
+
+                    let values: Vec<Rgb> = tiles[t]
+                        .pixels(view.width)
+                        .map(|idx| self.to_output(film.value(idx)))
+                        .collect();
+                    observer.tile_done(&tiles[t], &values);
+                }
+
+                if cancelled {
+                    bar.abandon();
+                    observer.cancelled(&film.stats(done, start, &sampled));
+                    return Ok(self.resolve(&film, &window));
+                }
+                progress.tiles_done = batch_end;
 
-                    let ray_origin = if self.defocus_angle <= 0.0 {
-                        camera_center
-                    } else {
-                        let p = rand_in_unit_disk(&mut thread_rng);
-                        camera_center + (p.x * defocus_disk_u) + (p.y * defocus_disk_v)
-                    };
+                if let Some(checkpoint) = &self.checkpoint
+                    && last_checkpoint.elapsed() >= checkpoint.interval
+                {
+                    self.save_checkpoint(checkpoint, &view, &window, &film, &progress, start)?;
+                    last_checkpoint = Instant::now();
+                }
+            }
 
-                    let ray_dir = pixel_sample - ray_origin;
-                    let ray = Ray::new(ray_origin, ray_dir);
+            progress.done = target;
+            progress.tiles_done = 0;
+            let stats = film.stats(target, start, &sampled);
+            observer.pass_done(&stats, &|| self.resolve(&film, &window));
+            if target >= self.samples_per_pixel {
+                break;
+            }
+            if let Some(target_noise) = self.target_noise
+                && stats.noise <= target_noise
+            {
+                break;
+            }
 
-                    pixel_color =
-                        pixel_color + ray_color(&ray, world, &mut thread_rng, self.max_depth);
-                    bar_clone.inc(1);
+            if let Some(adaptive) = &self.adaptive {
+                let stats: Vec<&PixelStats> = film.pixels.iter().map(|px| &px.stats).collect();
+                for tile_pixels in &mut progress.active {
+                    tile_pixels.retain(|&idx| !adaptive.converged(&stats, view.width, idx));
+                }
+                if progress.active.iter().all(Vec::is_empty) {
+                    break;
                 }
-                *pixel_ref = pixel_color * pixels_samples_scale;
-            });
+            }
+
+            progress.target = match self.pass_samples {
+                Some(pass_samples) => target.saturating_add(pass_samples.max(1)),
+                None => target.saturating_mul(2),
+            }
+            .min(self.samples_per_pixel);
+
+            if let Some(budget) = self.time_budget {
+                // Samples per pixel that fit in the time left, at the rate
+                // of the passes so far
+                let elapsed = start.elapsed();
+                let rate = film.samples as f64 / elapsed.as_secs_f64();
+                let sampling: usize = progress.active.iter().map(Vec::len).sum();
+                let fit = budget.saturating_sub(elapsed).as_secs_f64() * rate / sampling as f64;
+                if fit < 1.0 {
+                    break;
+                }
+                progress.target = progress.target.min(target.saturating_add(fit as u32));
+            }
+        }
 
         bar.finish();
-        img
+        Ok(self.resolve(&film, &window))
+    }
+
+    /// Splits a render into jobs for workers, by tile or into ranges of
+    /// `job_samples` samples. Jobs take every sample in one go, without
+    /// adaptive sampling or passes.
+    pub fn jobs(&self, split: JobSplit, job_samples: u32) -> anyhow::Result<Vec<Job>> {
+        let view = self.viewport();
+        let sampled = self
+            .crop_pixels()?
+            .grow(self.filter_reach(), view.width, view.height);
+        let spp = self.samples_per_pixel;
+
+        Ok(match split {
+            JobSplit::Tiles => tiles(&sampled, self.tile_size, self.tile_order)
+                .into_iter()
+                .map(|area| Job {
+                    area,
+                    samples: 0..spp,
+                })
+                .collect(),
+            JobSplit::Samples => (0..spp)
+                .step_by(job_samples.max(1) as usize)
+                .map(|start| Job {
+                    area: sampled,
+                    samples: start..start.saturating_add(job_samples.max(1)).min(spp),
+                })
+                .collect(),
+        })
+    }
+
+    /// Renders a job, the pixels of its area in parallel.
+    pub fn render_job(&self, world: &impl Hittable, job: &Job) -> JobResult {
+        let view = self.viewport();
+        let pixels: Vec<usize> = job.area.pixels(view.width).collect();
+        JobResult(
+            pixels
+                .par_iter()
+                .map(|&idx| self.render_samples(&view, world, idx, job.samples.clone()))
+                .collect(),
+        )
+    }
+
+    /// Most bytes the `JobResult` of `job` saves to, so a distributed render
+    /// can turn away larger results before reading them. Every sample may
+    /// splat and record a light once per path vertex.
+    pub fn max_result_size(&self, job: &Job) -> u64 {
+        let side = 2 * self.filter_reach() as u64 + 1;
+        let vertices = self.max_depth.max(0) as u64 + 2;
+        let empty: PixelSamples = (PixelState::new(self.aovs), Vec::new(), Vec::new());
+
+        let pixel = saved_len(&empty)
+            + side * side * saved_len(&(Rgb::BLACK, 0.0))
+            + job.samples.len() as u64
+                * vertices
+                * (saved_len(&(0usize, Rgb::BLACK)) + saved_len(&(LightId::Object(0), Rgb::BLACK)));
+        saved_len(&JobResult(Vec::new())) + (job.area.width * job.area.height) as u64 * pixel
+    }
+
+    /// Collects the results of `jobs` into an image.
+    pub fn merger<'a>(&'a self, jobs: &'a [Job]) -> anyhow::Result<JobMerger<'a>> {
+        let view = self.viewport();
+        let window = self.crop_pixels()?;
+        Ok(JobMerger {
+            camera: self,
+            jobs,
+            window,
+            sampled: window.grow(self.filter_reach(), view.width, view.height),
+            film: Film::new(view.width, view.height, self.aovs),
+            next: 0,
+            pending: BTreeMap::new(),
+            passed: 0,
+            start: Instant::now(),
+        })
+    }
+
+    /// Pixels the render covers: the crop window, or the whole image.
+    pub fn crop_pixels(&self) -> anyhow::Result<Tile> {
+        let view = self.viewport();
+        match &self.crop {
+            Some(crop) => crop.pixels(view.width, view.height),
+            None => Ok(Tile {
+                x: 0,
+                y: 0,
+                width: view.width,
+                height: view.height,
+            }),
+        }
+    }
+
+    /// The `window` of the whole image `img`, or `img` with everything
+    /// outside the window black with `crop_full_frame`.
+    fn crop_output(&self, img: Image, window: &Tile) -> Image {
+        if self.crop.is_none() {
+            return img;
+        }
+
+        let cropped = img.crop(window);
+        if !self.crop_full_frame {
+            return cropped;
+        }
+        let mut full = Image::new(img.width, img.height);
+        full.paste(&cropped, window.x, window.y);
+        full
+    }
+
+    /// Identifies the settings that decide which samples a render takes and
+    /// how they're accumulated, so a checkpoint isn't resumed with others.
+    fn fingerprint(&self, view: &Viewport, window: &Tile) -> u64 {
+        let (min_samples, threshold) = self.adaptive.as_ref().map_or((0, 0.0), |adaptive| {
+            (adaptive.min_samples, adaptive.threshold)
+        });
+
+        hash(&[
+            self.seed,
+            view.width as u64,
+            view.height as u64,
+            window.x as u64,
+            window.y as u64,
+            window.width as u64,
+            window.height as u64,
+            self.samples_per_pixel as u64,
+            min_samples as u64,
+            threshold.to_bits(),
+            self.pass_samples.map_or(0, |n| n as u64 + 1),
+            self.sampler as u64,
+            self.filter.kind as u64,
+            self.filter.radius.to_bits(),
+            self.tile_size as u64,
+            self.tile_order as u64,
+            self.spectral as u64,
+            self.aovs as u64,
+        ])
+    }
+
+    fn save_checkpoint(
+        &self,
+        checkpoint: &Checkpointing,
+        view: &Viewport,
+        window: &Tile,
+        film: &Film,
+        progress: &Progress,
+        start: Instant,
+    ) -> anyhow::Result<()> {
+        let mut body = Vec::new();
+        start.elapsed().save(&mut body);
+        progress.save(&mut body);
+        film.save(&mut body);
+
+        CheckpointFile {
+            seed: self.seed,
+            fingerprint: self.fingerprint(view, window),
+            args: checkpoint.args.clone(),
+            body,
+        }
+        .write(&checkpoint.path)
+    }
+
+    /// Loads a checkpoint's film and progress, and how long it had been
+    /// rendering.
+    fn load_checkpoint(
+        &self,
+        path: &Path,
+        view: &Viewport,
+        window: &Tile,
+    ) -> anyhow::Result<(Film, Progress, Duration)> {
+        let checkpoint = CheckpointFile::read(path)?;
+        ensure!(
+            checkpoint.fingerprint == self.fingerprint(view, window),
+            "the checkpoint at {} was made with different settings",
+            path.display()
+        );
+
+        let mut input = checkpoint.body.as_slice();
+        let elapsed = Duration::load(&mut input)?;
+        let progress = Progress::load(&mut input)?;
+        let film = Film::load(&mut input)?;
+        ensure!(
+            film.pixels.len() == view.width * view.height,
+            "the checkpoint's film doesn't match the image"
+        );
+
+        Ok((film, progress, elapsed))
+    }
+
+    /// Output colour of a film value: spectral renders accumulate XYZ, RGB
+    /// renders linear sRGB.
+    fn to_output(&self, film: Rgb) -> Rgb {
+        if self.spectral {
+            self.color_space.rgb_from_xyz(film)
+        } else {
+            self.color_space.rgb_from_linear_srgb(film)
+        }
+    }
+
+    /// Adds what `render_samples` returned for pixel `idx` to `film`.
+    fn add_samples(&self, film: &mut Film, idx: usize, (state, filtered, splats): PixelSamples) {
+        film.pixels[idx].merge(state);
+        self.add_filtered(&mut film.image, idx, filtered);
+        for (pixel, splat) in splats {
+            film.splats[pixel] = film.splats[pixel] + splat;
+        }
+    }
+
+    /// The image as far as `film` has rendered it, with its layers, cropped
+    /// to `window`.
+    fn resolve(&self, film: &Film, window: &Tile) -> Image {
+        let mut img = Image::new(film.image.width, film.image.height);
+        let black = || vec![Rgb::BLACK; film.pixels.len()];
+
+        // Layers are filled here and added to the image once they're done,
+        // the AOV layers with the first pixel that has AOVs and the light
+        // layers in the order the lights turn up
+        let mut samples = self.adaptive.is_some().then(black);
+        let mut aov_layers = Vec::new();
+        let mut light_layers: Vec<(LightId, Vec<Rgb>)> = Vec::new();
+
+        for (idx, px) in film.pixels.iter().enumerate() {
+            let n = px.stats.count as f64;
+            img.as_mut_slice()[idx] = self.to_output(film.value(idx));
+
+            if let Some(samples) = &mut samples {
+                samples[idx] = Rgb::new(n, n, n);
+            }
+
+            // Pixels without samples, outside the crop window or not reached
+            // by a cancelled render, keep black layers
+            let Some(mut aovs) = px.aovs.clone().filter(|_| n > 0.0) else {
+                continue;
+            };
+            aovs.scale(1.0 / n);
+
+            // Variance of the pixel's mean luminance, infinite below 2 samples
+            let variance = px.stats.standard_error().powi(2);
+            let values = [
+                self.color_space.rgb_from_linear_srgb(aovs.albedo),
+                aovs.normal,
+                Rgb::new(aovs.depth, aovs.depth, aovs.depth),
+                self.to_output(aovs.emission),
+                self.to_output(aovs.direct),
+                self.to_output(aovs.indirect),
+                Rgb::new(aovs.occlusion, aovs.occlusion, aovs.occlusion),
+                Rgb::new(variance, variance, variance),
+            ];
+            if aov_layers.is_empty() {
+                aov_layers = values.iter().map(|_| black()).collect();
+            }
+            for (layer, value) in aov_layers.iter_mut().zip(values) {
+                layer[idx] = value;
+            }
+
+            for (light, radiance) in aovs.lights {
+                let at = match light_layers.iter().position(|(l, _)| *l == light) {
+                    Some(at) => at,
+                    None => {
+                        light_layers.push((light, black()));
+                        light_layers.len() - 1
+                    }
+                };
+                light_layers[at].1[idx] = self.to_output(radiance);
+            }
+        }
+
+        if let Some(samples) = samples {
+            img.add_layer("samples", samples);
+        }
+        let aov_names = [
+            "albedo",
+            "normal",
+            "depth",
+            "emission",
+            "direct",
+            "indirect",
+            "occlusion",
+            "variance",
+        ];
+        for (name, layer) in aov_names.into_iter().zip(aov_layers) {
+            img.add_layer(name, layer);
+        }
+        for (light, layer) in light_layers {
+            let name = match light {
+                LightId::Background => "light_background".to_string(),
+                LightId::Object(id) => format!("light_{id}"),
+            };
+            img.add_layer(name, layer);
+        }
+
+        self.crop_output(img, window)
+    }
+
+    /// Adds the filtered samples `render_samples` returned for pixel `idx` to
+    /// the film in `img`, dropping those outside the image.
+    fn add_filtered(&self, img: &mut Image, idx: usize, filtered: Footprint) {
+        let reach = self.filter_reach() as isize;
+        let side = 2 * reach + 1;
+        let (i, j) = ((idx % img.width) as isize, (idx / img.width) as isize);
+
+        for (k, (sum, weight)) in filtered.into_iter().enumerate() {
+            let x = i + k as isize % side - reach;
+            let y = j + k as isize / side - reach;
+            if weight == 0.0
+                || !(0..img.width as isize).contains(&x)
+                || !(0..img.height as isize).contains(&y)
+            {
+                continue;
+            }
+            img.add_weighted(y as usize * img.width + x as usize, sum, weight);
+        }
+    }
+}
+
+/// Luminance of a film value: Y for spectral renders' XYZ, the Rec. 709
+/// weighted sum of linear sRGB otherwise.
+pub fn film_luminance(film: Rgb, spectral: bool) -> f64 {
+    let y = if spectral {
+        film.y
+    } else {
+        0.2126 * film.r() + 0.7152 * film.g() + 0.0722 * film.b()
+    };
+    y.max(0.0)
+}
+
+/// Film value of a sample's radiance: CIE XYZ for spectral samples, linear
+/// sRGB otherwise.
+pub fn to_film(radiance: Rgb, wavelengths: Option<SampledWavelengths>) -> Rgb {
+    match wavelengths {
+        Some(wl) => wl.radiance_to_xyz(radiance),
+        None => radiance,
+    }
+}
+
+/// Render progress bar counting samples.
+pub fn progress_bar(len: u64) -> ProgressBar {
+    let bar = ProgressBar::new(len);
+
+    bar.set_style(
+        ProgressStyle::with_template( "{spinner:.green} [{elapsed_precise}/{eta_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {percent}% {per_sec}")
+            .unwrap(),
+    );
+    bar
+}
+
+/// Splits `items` into consecutive batches of `size` to render in parallel.
+/// Adding a batch's results to the film in item order before starting the
+/// next keeps the floating point sums independent of thread scheduling, so a
+/// render comes out the same with any number of threads.
+pub fn batches(items: Range<usize>, size: usize) -> impl Iterator<Item = Range<usize>> {
+    let end = items.end;
+    items
+        .step_by(size)
+        .map(move |start| start..(start + size).min(end))
+}
+
+/// Camera frame derived from the camera settings for one render. Pixels lie
+/// on the focus plane, and camera rays start on the lens disk around `center`.
+pub struct Viewport {
+    width: usize,
+    height: usize,
+    center: Point3,
+    pixel_origin: Point3,
+    pixel_delta_u: Vec3,
+    pixel_delta_v: Vec3,
+    defocus_disk_u: Vec3,
+    defocus_disk_v: Vec3,
+    forward: Vec3,
+    focus_dist: f64,
+    /// Area of the whole image on the focus plane.
+    image_area: f64,
+}
+
+impl Viewport {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.forward
+    }
+
+    /// Picks a point on the lens uniformly, like camera rays do.
+    pub fn sample_lens(&self, rng: &mut impl Rng) -> Point3 {
+        let p = rand_in_unit_disk(rng);
+        self.center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+    }
+
+    /// Index of the pixel a camera ray from lens point `origin` in direction
+    /// `dir` passes through, if any.
+    pub fn pixel_at(&self, origin: Point3, dir: Vec3) -> Option<usize> {
+        let cos = dot(dir, self.forward);
+        if cos <= 0.0 {
+            return None;
+        }
+
+        // Camera samples for pixel (i, j) are offset by [0, 1) pixels from
+        // `pixel_origin`, so that's where the footprint of pixel (0, 0) starts.
+        let on_plane = origin + dir * (self.focus_dist / cos);
+        let rel = on_plane - self.pixel_origin;
+
+        let x = dot(rel, self.pixel_delta_u) / self.pixel_delta_u.len_sqrd();
+        let y = dot(rel, self.pixel_delta_v) / self.pixel_delta_v.len_sqrd();
+
+        if (0.0..self.width as f64).contains(&x) && (0.0..self.height as f64).contains(&y) {
+            Some(y as usize * self.width + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Solid angle density with which camera rays from `origin` take `dir`.
+    pub fn pdf(&self, origin: Point3, dir: Vec3) -> f64 {
+        if self.pixel_at(origin, dir).is_none() {
+            return 0.0;
+        }
+
+        let cos = dot(norm(dir), self.forward);
+        self.focus_dist * self.focus_dist / (self.image_area * cos * cos * cos)
+    }
+
+    /// Importance the lens point `origin` receives from `target`, divided by
+    /// the solid angle density of having picked `origin` as seen from
+    /// `target`. Normalised so every pixel adds up splats of all its samples.
+    pub fn importance(&self, origin: Point3, target: Point3) -> f64 {
+        let dir = target - origin;
+        if self.pixel_at(origin, dir).is_none() {
+            return 0.0;
+        }
+
+        let cos = dot(norm(dir), self.forward);
+        self.focus_dist * self.focus_dist / (self.image_area * cos * cos * cos * dir.len_sqrd())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aabb::Aabb,
+        bdpt::Bdpt,
+        checkpoint::tests::round_trip,
+        hittable::{Hittables, Sphere},
+        material::Material,
+        mlt::Mlt,
+        spectrum::{Emission, Spd},
+        sppm::Sppm,
+        volume::{GridVolume, VolumeGrids, VoxelGrid},
+    };
+
+    /// A ground, a light and a voxel cloud in front of the default camera.
+    fn test_world() -> Hittables {
+        let mut world = Hittables::new();
+        world.add(Sphere::new(
+            Point3::new(0.0, -100.5, -1.0),
+            100.0,
+            Material::Lambertian {
+                albedo: Rgb::new(0.8, 0.8, 0.8),
+            },
+        ));
+        world.add(Sphere::new(
+            Point3::new(0.4, 0.0, -1.0),
+            0.3,
+            Material::DiffuseLight {
+                emission: Emission::new(Spd::D65, 4.0),
+            },
+        ));
+        let density = VoxelGrid::from_fn([8, 8, 8], |uvw| {
+            (1.0 - (uvw - Vec3::new(0.5, 0.5, 0.5)).len() * 2.0).max(0.0)
+        });
+        world.add(GridVolume::new(
+            Aabb::new(Point3::new(-0.8, -0.4, -1.4), Point3::new(0.0, 0.4, -0.6)),
+            VolumeGrids {
+                density,
+                emission: None,
+                temperature: None,
+            },
+        ));
+        world
+    }
+
+    #[test]
+    fn film_round_trips() {
+        let mut film = Film::new(3, 2, true);
+        film.image.add_weighted(1, Rgb::new(0.2, 0.4, 0.6), 0.5);
+        film.splats[4] = Rgb::new(1.0, 0.0, 0.5);
+        film.samples = 6;
+        let pixel = &mut film.pixels[1];
+        pixel.stats.add(0.25);
+        pixel.stats.add(0.75);
+        let mut sample = Aovs::new();
+        sample.albedo = Rgb::new(0.5, 0.5, 0.5);
+        sample.depth = 3.0;
+        pixel
+            .aovs
+            .as_mut()
+            .unwrap()
+            .accumulate(&sample, |film| film);
+
+        let loaded = round_trip(&film);
+        assert_eq!(loaded.samples, 6);
+        for idx in 0..6 {
+            assert_eq!(loaded.value(idx), film.value(idx));
+        }
+        let pixel = &loaded.pixels[1];
+        assert_eq!((pixel.stats.count, pixel.stats.mean), (2, 0.5));
+        let aovs = pixel.aovs.as_ref().unwrap();
+        assert_eq!((aovs.albedo, aovs.depth), (Rgb::new(0.5, 0.5, 0.5), 3.0));
+        assert!(loaded.pixels[0].aovs.is_some());
+    }
+
+    #[test]
+    fn truncated_film_is_an_error() {
+        let mut bytes = Vec::new();
+        Film::new(2, 2, false).save(&mut bytes);
+        bytes.pop();
+        assert!(Film::load(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn job_results_fit_their_size_limit() {
+        let mut camera = Camera::new(16.0 / 9.0, 24, 8, 6);
+        camera.integrator = Box::new(Bdpt::new(6));
+        camera.aovs = true;
+        camera.filter = Filter::new(FilterKind::Lanczos);
+        camera.seed = 1;
+
+        let world = test_world();
+
+        for split in [JobSplit::Tiles, JobSplit::Samples] {
+            for job in camera.jobs(split, 4).unwrap() {
+                let result = camera.render_job(&world, &job);
+                assert!(saved_len(&result) <= camera.max_result_size(&job));
+            }
+        }
+    }
+
+    #[test]
+    fn renders_dont_depend_on_the_thread_count() {
+        struct Quiet;
+        impl RenderObserver for Quiet {}
+
+        let path = || PathTracer {
+            max_depth: 6,
+            roulette_depth: 3,
+        };
+        let mut sppm = Sppm::new(6, 0.1);
+        sppm.photons_per_iteration = Some(5000);
+        let mut mlt = Mlt::new(path());
+        mlt.chains = 8;
+        mlt.bootstrap_samples = 64;
+        let integrators: Vec<Box<dyn Integrator>> = vec![
+            Box::new(path()),
+            Box::new(Bdpt::new(6)),
+            Box::new(sppm),
+            Box::new(mlt),
+        ];
+
+        let world = test_world();
+        for integrator in integrators {
+            let mut camera = Camera::new(16.0 / 9.0, 24, 4, 6);
+            camera.integrator = integrator;
+            camera.seed = 1;
+
+            let render = |threads| {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                let image = pool.install(|| camera.render(&world, &mut Quiet).unwrap());
+                image.iter().copied().collect::<Vec<_>>()
+            };
+            assert_eq!(render(1), render(4));
+        }
     }
 }
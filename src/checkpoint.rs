@@ -0,0 +1,330 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, ensure};
+
+use crate::vec3::Vec3;
+
+/// Periodically saves a render's progress, so it can be resumed after a
+/// crash. Samples draw their random numbers from the seed and their pixel
+/// and sample index, so the seed is all the random state there is to save.
+pub struct Checkpointing {
+    pub path: PathBuf,
+    /// Time between checkpoints.
+    pub interval: Duration,
+    /// Continue from the checkpoint at `path` instead of starting over.
+    pub resume: bool,
+    /// Command line of the render, saved so a resume can be checked against
+    /// it.
+    pub args: Vec<String>,
+}
+
+impl Checkpointing {
+    pub fn new(path: PathBuf, interval: Duration, resume: bool, args: Vec<String>) -> Self {
+        Self {
+            path,
+            interval,
+            resume,
+            args,
+        }
+    }
+}
+
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+/// Contents of a checkpoint file. `fingerprint` identifies the camera
+/// settings the render was started with, `args` its whole command line and
+/// `body` holds its progress.
+pub struct CheckpointFile {
+    pub seed: u64,
+    pub fingerprint: u64,
+    pub args: Vec<String>,
+    pub body: Vec<u8>,
+}
+
+impl CheckpointFile {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Unable to read the checkpoint at {}", path.display()))?;
+        ensure!(
+            bytes.starts_with(MAGIC),
+            "{} isn't a checkpoint",
+            path.display()
+        );
+
+        let mut input = &bytes[MAGIC.len()..];
+        Ok(Self {
+            seed: u64::load(&mut input)?,
+            fingerprint: u64::load(&mut input)?,
+            args: Vec::load(&mut input)?,
+            body: input.to_vec(),
+        })
+    }
+
+    /// Writes the checkpoint next to `path` first and then moves it there, so
+    /// a crash while writing leaves the previous checkpoint intact.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        self.seed.save(&mut bytes);
+        self.fingerprint.save(&mut bytes);
+        self.args.save(&mut bytes);
+        bytes.extend_from_slice(&self.body);
+
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, bytes).with_context(|| {
+            format!("Unable to write the checkpoint at {}", temporary.display())
+        })?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Unable to move the checkpoint to {}", path.display()))
+    }
+}
+
+/// Values saved to and loaded back from checkpoints, little-endian. Render
+/// workers exchange them too.
+pub trait Checkpointed: Sized {
+    fn save(&self, out: &mut Vec<u8>);
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self>;
+}
+
+fn take<const N: usize>(input: &mut &[u8]) -> anyhow::Result<[u8; N]> {
+    ensure!(input.len() >= N, "the checkpoint is truncated");
+    let (bytes, rest) = input.split_at(N);
+    *input = rest;
+    Ok(bytes.try_into().expect("split at N"))
+}
+
+impl Checkpointed for u32 {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self::from_le_bytes(take(input)?))
+    }
+}
+
+impl Checkpointed for u64 {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self::from_le_bytes(take(input)?))
+    }
+}
+
+impl Checkpointed for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u64).save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(u64::load(input)?.try_into()?)
+    }
+}
+
+impl Checkpointed for f64 {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.to_bits().save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self::from_bits(u64::load(input)?))
+    }
+}
+
+impl Checkpointed for Duration {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.as_secs_f64().save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self::try_from_secs_f64(f64::load(input)?)?)
+    }
+}
+
+impl Checkpointed for Vec3 {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.x.save(out);
+        self.y.save(out);
+        self.z.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self::new(
+            f64::load(input)?,
+            f64::load(input)?,
+            f64::load(input)?,
+        ))
+    }
+}
+
+impl<A: Checkpointed, B: Checkpointed> Checkpointed for (A, B) {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+        self.1.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok((A::load(input)?, B::load(input)?))
+    }
+}
+
+impl<A: Checkpointed, B: Checkpointed, C: Checkpointed> Checkpointed for (A, B, C) {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+        self.1.save(out);
+        self.2.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok((A::load(input)?, B::load(input)?, C::load(input)?))
+    }
+}
+
+impl Checkpointed for String {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        let len = usize::load(input)?;
+        ensure!(len <= input.len(), "the checkpoint is truncated");
+        let (bytes, rest) = input.split_at(len);
+        *input = rest;
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+}
+
+impl<T: Checkpointed> Checkpointed for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                out.push(1);
+                value.save(out);
+            }
+            None => out.push(0),
+        }
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(match take::<1>(input)? {
+            [0] => None,
+            _ => Some(T::load(input)?),
+        })
+    }
+}
+
+impl<T: Checkpointed> Checkpointed for Vec<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for value in self {
+            value.save(out);
+        }
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        let len = usize::load(input)?;
+        // Every value takes at least a byte, which keeps a corrupt length
+        // from allocating everything
+        ensure!(len <= input.len(), "the checkpoint is truncated");
+        (0..len).map(|_| T::load(input)).collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Saves `value`, loads it back and checks the copy saves the same bytes
+    /// and that loading used all of them.
+    pub(crate) fn round_trip<T: Checkpointed>(value: &T) -> T {
+        let mut bytes = Vec::new();
+        value.save(&mut bytes);
+
+        let mut input = bytes.as_slice();
+        let loaded = T::load(&mut input).unwrap();
+        assert!(input.is_empty(), "{} bytes left over", input.len());
+
+        let mut again = Vec::new();
+        loaded.save(&mut again);
+        assert_eq!(bytes, again);
+        loaded
+    }
+
+    #[test]
+    fn vec_round_trips() {
+        assert_eq!(round_trip(&vec![1u32, 2, 3]), [1, 2, 3]);
+        assert!(round_trip(&Vec::<u64>::new()).is_empty());
+        assert_eq!(
+            round_trip(&vec![vec![0usize], vec![], vec![7, 8]]),
+            [vec![0], vec![], vec![7, 8]]
+        );
+    }
+
+    #[test]
+    fn option_round_trips() {
+        assert_eq!(round_trip(&Some(2.5f64)), Some(2.5));
+        assert_eq!(round_trip(&None::<f64>), None);
+    }
+
+    #[test]
+    fn string_round_trips() {
+        assert_eq!(round_trip(&String::new()), "");
+        assert_eq!(
+            round_trip(&"--scene glass ünïcode".to_string()),
+            "--scene glass ünïcode"
+        );
+    }
+
+    #[test]
+    fn tuples_and_floats_round_trip() {
+        let value = (
+            Vec3::new(1.0, -0.0, f64::INFINITY),
+            Duration::from_millis(1500),
+            9u64,
+        );
+        let (v, duration, n) = round_trip(&value);
+        assert_eq!(
+            (v.x, v.y.to_bits(), v.z),
+            (1.0, (-0.0f64).to_bits(), f64::INFINITY)
+        );
+        assert_eq!((duration, n), (Duration::from_millis(1500), 9));
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let mut bytes = Vec::new();
+        (vec![1u64, 2, 3], "name".to_string(), Some(4u32)).save(&mut bytes);
+
+        for len in 0..bytes.len() {
+            let mut input = &bytes[..len];
+            assert!(
+                <(Vec<u64>, String, Option<u32>)>::load(&mut input).is_err(),
+                "loaded {len} of {} bytes",
+                bytes.len()
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_lengths_are_an_error() {
+        let mut bytes = Vec::new();
+        u64::MAX.save(&mut bytes);
+        assert!(Vec::<u32>::load(&mut bytes.as_slice()).is_err());
+        assert!(String::load(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn corrupt_durations_are_an_error() {
+        for secs in [-1.0, f64::NAN, f64::INFINITY] {
+            let mut bytes = Vec::new();
+            secs.save(&mut bytes);
+            assert!(Duration::load(&mut bytes.as_slice()).is_err(), "{secs}");
+        }
+    }
+}
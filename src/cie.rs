@@ -0,0 +1,130 @@
+//! CIE colour matching functions, D65 daylight and the sigmoid spectra RGB
+//! colours are upsampled to, on plain arrays. Nothing here depends on the rest
+//! of the crate, so the build script includes this module on its own to fit
+//! the RGB to spectrum table.
+
+use std::sync::LazyLock;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// Correlated colour temperature of CIE standard illuminant D65.
+pub const D65_CCT: f64 = 6504.0;
+
+fn piecewise_gaussian(x: f64, mu: f64, sigma_lo: f64, sigma_hi: f64) -> f64 {
+    let sigma = if x < mu { sigma_lo } else { sigma_hi };
+    let t = (x - mu) / sigma;
+    f64::exp(-0.5 * t * t)
+}
+
+/// CIE 1931 2° colour matching functions at `lambda` (nm), using the multi-lobe
+/// Gaussian fit from Wyman, Sloan & Shirley (2013).
+pub fn xyz(lambda: f64) -> [f64; 3] {
+    let x = 1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8);
+
+    [x, y, z]
+}
+
+pub fn xyz_to_linear_srgb([x, y, z]: [f64; 3]) -> [f64; 3] {
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+/// Integral of the ȳ matching function, used to normalise spectral radiance to
+/// XYZ so that a flat unit spectrum has Y = 1.
+pub static CIE_Y_INTEGRAL: LazyLock<f64> = LazyLock::new(|| integrate(|lambda| xyz(lambda)[1]));
+
+/// Sum of `f` over the visible range in 1 nm steps.
+pub fn integrate(f: impl Fn(f64) -> f64) -> f64 {
+    let mut sum = 0.0;
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        sum += f(lambda);
+        lambda += 1.0;
+    }
+    sum
+}
+
+const DAYLIGHT_START: f64 = 380.0;
+const DAYLIGHT_STEP: f64 = 10.0;
+
+/// CIE daylight basis functions S0, S1, S2 from 380 to 780 nm in 10 nm steps.
+const DAYLIGHT_S0: [f64; 41] = [
+    63.4, 65.8, 94.8, 104.8, 105.9, 96.8, 113.9, 125.6, 125.5, 121.3, 121.3, 113.5, 113.1, 110.8,
+    106.5, 108.8, 105.3, 104.4, 100.0, 96.0, 95.1, 89.1, 90.5, 90.3, 88.4, 84.0, 85.1, 81.9, 82.6,
+    84.9, 81.3, 71.9, 74.3, 76.4, 63.3, 71.7, 77.0, 65.2, 47.7, 68.6, 65.0,
+];
+const DAYLIGHT_S1: [f64; 41] = [
+    38.5, 35.0, 43.4, 46.3, 43.9, 37.1, 36.7, 35.9, 32.6, 27.9, 24.3, 20.1, 16.2, 13.2, 8.6, 6.1,
+    4.2, 1.9, 0.0, -1.6, -3.5, -3.5, -5.8, -7.2, -8.6, -9.5, -10.9, -10.7, -12.0, -14.0, -13.6,
+    -12.0, -13.3, -12.9, -10.6, -11.6, -12.2, -10.2, -7.8, -11.2, -10.4,
+];
+const DAYLIGHT_S2: [f64; 41] = [
+    3.0, 1.2, -1.1, -0.5, -0.7, -1.2, -2.6, -2.9, -2.8, -2.6, -2.6, -1.8, -1.5, -1.3, -1.2, -1.0,
+    -0.5, -0.3, 0.0, 0.2, 0.5, 2.1, 3.2, 4.1, 4.7, 5.1, 6.7, 7.3, 8.6, 9.8, 10.2, 8.3, 9.6, 8.5,
+    7.0, 7.6, 8.0, 6.7, 5.2, 7.4, 6.8,
+];
+
+/// Linear interpolation into a regularly sampled table, clamped at both ends.
+pub fn sample_table(table: &[f64], start: f64, step: f64, lambda: f64) -> f64 {
+    let x = ((lambda - start) / step).clamp(0.0, (table.len() - 1) as f64);
+    let i = (x.floor() as usize).min(table.len() - 2);
+    let t = x - i as f64;
+
+    table[i] + (table[i + 1] - table[i]) * t
+}
+
+/// CIE D-series daylight at a correlated colour temperature, clamped to
+/// 4000 K to 25000 K.
+pub fn daylight(cct: f64, lambda: f64) -> f64 {
+    let t = cct.clamp(4000.0, 25000.0);
+    let xd = if t <= 7000.0 {
+        -4.6070e9 / t.powi(3) + 2.9678e6 / t.powi(2) + 0.09911e3 / t + 0.244063
+    } else {
+        -2.0064e9 / t.powi(3) + 1.9018e6 / t.powi(2) + 0.24748e3 / t + 0.237040
+    };
+    let yd = -3.0 * xd * xd + 2.870 * xd - 0.275;
+    let m = 0.0241 + 0.2562 * xd - 0.7341 * yd;
+    let m1 = (-1.3515 - 1.7703 * xd + 5.9114 * yd) / m;
+    let m2 = (0.0300 - 31.4424 * xd + 30.0717 * yd) / m;
+
+    let table = |t: &[f64]| sample_table(t, DAYLIGHT_START, DAYLIGHT_STEP, lambda);
+    table(&DAYLIGHT_S0) + m1 * table(&DAYLIGHT_S1) + m2 * table(&DAYLIGHT_S2)
+}
+
+/// D65 scaled to unit luminance. RGB colours are defined relative to this white.
+pub fn d65_normalized(lambda: f64) -> f64 {
+    static SCALE: LazyLock<f64> = LazyLock::new(|| {
+        let y = integrate(|lambda| daylight(D65_CCT, lambda) * xyz(lambda)[1]);
+        1.0 / (y / *CIE_Y_INTEGRAL)
+    });
+    daylight(D65_CCT, lambda) * *SCALE
+}
+
+/// Smooth, bounded spectrum `s(λ) = sigmoid(c0 t² + c1 t + c2)` with `t` the
+/// wavelength normalised to the visible range (Jakob & Hanika 2019).
+#[derive(Clone, Copy, Debug)]
+pub struct SigmoidPolynomial {
+    pub c: [f64; 3],
+}
+
+impl SigmoidPolynomial {
+    pub fn eval(&self, lambda: f64) -> f64 {
+        let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+        let x = (self.c[0] * t + self.c[1]) * t + self.c[2];
+
+        if x.is_infinite() {
+            return if x > 0.0 { 1.0 } else { 0.0 };
+        }
+        0.5 + x / (2.0 * f64::sqrt(1.0 + x * x))
+    }
+}
@@ -0,0 +1,59 @@
+use clap::ValueEnum;
+
+use crate::{rbg::Rgb, spectrum::xyz_to_linear_srgb, vec3::Vec3};
+
+/// RGB colour spaces the renderer can output. All share the D65 white point,
+/// so converting between them needs no chromatic adaptation.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    Rec2020,
+}
+
+type Matrix = [[f64; 3]; 3];
+
+fn apply(m: &Matrix, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+const LINEAR_SRGB_TO_XYZ: Matrix = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+
+const XYZ_TO_DISPLAY_P3: Matrix = [
+    [2.4934969, -0.9313836, -0.4027108],
+    [-0.8294890, 1.7626641, 0.0236247],
+    [0.0358458, -0.0761724, 0.9568845],
+];
+
+const XYZ_TO_REC2020: Matrix = [
+    [1.7166512, -0.3556708, -0.2533663],
+    [-0.6666844, 1.6164812, 0.0157685],
+    [0.0176399, -0.0427706, 0.9421031],
+];
+
+impl ColorSpace {
+    /// Linear RGB in this colour space for a CIE XYZ colour.
+    pub fn rgb_from_xyz(&self, xyz: Vec3) -> Rgb {
+        match self {
+            Self::Srgb => xyz_to_linear_srgb(xyz),
+            Self::DisplayP3 => apply(&XYZ_TO_DISPLAY_P3, xyz),
+            Self::Rec2020 => apply(&XYZ_TO_REC2020, xyz),
+        }
+    }
+
+    /// Linear RGB in this colour space for a linear sRGB colour.
+    pub fn rgb_from_linear_srgb(&self, rgb: Rgb) -> Rgb {
+        match self {
+            Self::Srgb => rgb,
+            _ => self.rgb_from_xyz(apply(&LINEAR_SRGB_TO_XYZ, rgb)),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::ensure;
+
+use crate::tile::Tile;
+
+/// Part of the image to render.
+#[derive(Clone, Copy, Debug)]
+pub enum CropWindow {
+    Pixels(Tile),
+    /// Corners as fractions of the image's width and height, from 0 to 1.
+    Normalized {
+        min: (f64, f64),
+        max: (f64, f64),
+    },
+}
+
+impl CropWindow {
+    /// Pixels of a `width` by `height` image the window covers. Normalized
+    /// windows take the pixels whose top left corner they contain, so
+    /// windows that share an edge don't share pixels.
+    pub fn pixels(&self, width: usize, height: usize) -> anyhow::Result<Tile> {
+        let tile = match *self {
+            CropWindow::Pixels(tile) => tile,
+            CropWindow::Normalized { min, max } => {
+                ensure!(
+                    (0.0..=1.0).contains(&min.0)
+                        && (0.0..=1.0).contains(&min.1)
+                        && (0.0..=1.0).contains(&max.0)
+                        && (0.0..=1.0).contains(&max.1),
+                    "crop window corners must be between 0 and 1"
+                );
+                let edge = |fraction: f64, size: usize| (fraction * size as f64).ceil() as usize;
+                let (x, y) = (edge(min.0, width), edge(min.1, height));
+                Tile {
+                    x,
+                    y,
+                    width: edge(max.0, width).saturating_sub(x),
+                    height: edge(max.1, height).saturating_sub(y),
+                }
+            }
+        };
+
+        ensure!(
+            tile.width > 0 && tile.height > 0,
+            "the crop window doesn't cover any pixels"
+        );
+        let fits = |start: usize, size: usize, limit: usize| {
+            start.checked_add(size).is_some_and(|end| end <= limit)
+        };
+        ensure!(
+            fits(tile.x, tile.width, width) && fits(tile.y, tile.height, height),
+            "the crop window reaches outside the {width}x{height} image"
+        );
+        Ok(tile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(x: usize, y: usize, width: usize, height: usize) -> CropWindow {
+        CropWindow::Pixels(Tile {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    #[test]
+    fn pixel_windows_must_fit_the_image() {
+        let tile = pixels(2, 3, 4, 5).pixels(6, 8).unwrap();
+        assert_eq!((tile.x, tile.y, tile.width, tile.height), (2, 3, 4, 5));
+
+        assert!(pixels(3, 0, 4, 1).pixels(6, 8).is_err());
+        assert!(pixels(0, 4, 1, 5).pixels(6, 8).is_err());
+        assert!(pixels(0, 0, 0, 1).pixels(6, 8).is_err());
+    }
+
+    #[test]
+    fn overflowing_windows_are_out_of_bounds() {
+        assert!(pixels(usize::MAX, 0, 2, 2).pixels(6, 8).is_err());
+        assert!(pixels(0, usize::MAX, 2, 2).pixels(6, 8).is_err());
+        assert!(pixels(1, 1, usize::MAX, 2).pixels(6, 8).is_err());
+    }
+
+    #[test]
+    fn normalized_windows_sharing_an_edge_share_no_pixels() {
+        let (width, height) = (7, 5);
+        let window = |min, max| {
+            CropWindow::Normalized { min, max }
+                .pixels(width, height)
+                .unwrap()
+        };
+
+        for split in [0.2, 1.0 / 3.0, 0.5, 0.7] {
+            let left = window((0.0, 0.0), (split, 1.0));
+            let right = window((split, 0.0), (1.0, 1.0));
+            assert_eq!(left.x + left.width, right.x, "split at {split}");
+            assert_eq!(left.width + right.width, width);
+
+            let top = window((0.0, 0.0), (1.0, split));
+            let bottom = window((0.0, split), (1.0, 1.0));
+            assert_eq!(top.y + top.height, bottom.y, "split at {split}");
+            assert_eq!(top.height + bottom.height, height);
+        }
+    }
+
+    #[test]
+    fn normalized_windows_must_be_inside_the_unit_square() {
+        let window = |min, max| CropWindow::Normalized { min, max }.pixels(7, 5);
+        assert!(window((-0.1, 0.0), (1.0, 1.0)).is_err());
+        assert!(window((0.0, 0.0), (1.0, 1.5)).is_err());
+        assert!(window((0.5, 0.5), (0.5, 1.0)).is_err());
+    }
+}
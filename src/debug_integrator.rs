@@ -0,0 +1,129 @@
+use clap::ValueEnum;
+use rand::RngCore;
+
+use crate::{
+    hittable::Hittable,
+    integrator::{Background, Integrator},
+    ray::Ray,
+    rbg::Rgb,
+    rgb_to_spectrum::illuminant,
+    vec3::lerp,
+};
+
+/// What a `DebugIntegrator` shows.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DebugChannel {
+    /// Shading normal, mapped from [-1, 1] to [0, 1]
+    Normal,
+    /// Distance to the first hit, white up close fading to black at `max_distance`
+    Depth,
+    /// Green for front faces, red for back faces
+    FrontFace,
+    /// Material colour without any lighting
+    Albedo,
+    /// Surface parameterisation as red/green
+    Uv,
+    /// A distinct colour per top-level object
+    ObjectId,
+    /// Heatmap of how many bounces the path survives, up to `max_depth`
+    Bounces,
+}
+
+/// Visualises geometry and material data from the first `HitRecord` instead of
+/// computing light transport. Misses are black.
+pub struct DebugIntegrator {
+    pub channel: DebugChannel,
+    pub max_distance: f64,
+    pub max_depth: i32,
+}
+
+impl DebugIntegrator {
+    pub fn new(channel: DebugChannel, max_depth: i32) -> Self {
+        Self {
+            channel,
+            max_distance: 30.0,
+            max_depth,
+        }
+    }
+
+    fn bounces(&self, mut ray: Ray, world: &dyn Hittable, mut rng: &mut dyn RngCore) -> i32 {
+        for depth in 0..self.max_depth {
+            let Some(h) = world.hit(&ray, 0.00001..f64::INFINITY) else {
+                return depth;
+            };
+            let Some((_, new_ray)) = h.mat.scatter(&ray, &h, &mut rng) else {
+                return depth;
+            };
+            ray = new_ray;
+        }
+
+        self.max_depth
+    }
+}
+
+/// Blue → green → red ramp for `t` in `[0, 1]`.
+pub fn heatmap(t: f64) -> Rgb {
+    let t = t.clamp(0.0, 1.0);
+    let blue = Rgb::new(0.0, 0.0, 1.0);
+    let green = Rgb::new(0.0, 1.0, 0.0);
+    let red = Rgb::new(1.0, 0.0, 0.0);
+
+    if t < 0.5 {
+        lerp(&blue, &green, 2.0 * t)
+    } else {
+        lerp(&green, &red, 2.0 * t - 1.0)
+    }
+}
+
+/// Spreads consecutive ids over well separated colours.
+fn id_color(id: usize) -> Rgb {
+    let mut h = (id as u64)
+        .wrapping_add(1)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h ^= h >> 29;
+
+    let channel = |shift: u32| ((h >> shift) & 0xff) as f64 / 255.0;
+    Rgb::new(channel(0), channel(8), channel(16))
+}
+
+impl Integrator for DebugIntegrator {
+    fn radiance(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        _background: &Background,
+        rng: &mut dyn RngCore,
+    ) -> Rgb {
+        let wavelengths = ray.wavelengths();
+
+        let color = if let DebugChannel::Bounces = self.channel {
+            heatmap(self.bounces(ray, world, rng) as f64 / self.max_depth as f64)
+        } else if let Some(h) = world.hit(&ray, 0.00001..f64::INFINITY) {
+            match self.channel {
+                DebugChannel::Normal => 0.5 * (h.n + 1.0),
+                DebugChannel::Depth => {
+                    let distance = h.t * ray.direction().len();
+                    let v = 1.0 - (distance / self.max_distance).clamp(0.0, 1.0);
+                    Rgb::new(v, v, v)
+                }
+                DebugChannel::FrontFace => {
+                    if h.front_face {
+                        Rgb::new(0.0, 1.0, 0.0)
+                    } else {
+                        Rgb::new(1.0, 0.0, 0.0)
+                    }
+                }
+                DebugChannel::Albedo => h.mat.albedo(),
+                DebugChannel::Uv => Rgb::new(h.uv.0, h.uv.1, 0.0),
+                DebugChannel::ObjectId => id_color(h.object_id),
+                DebugChannel::Bounces => Rgb::BLACK,
+            }
+        } else {
+            Rgb::BLACK
+        };
+
+        // Spectral renders expect per-wavelength values, so the debug colour is
+        // upsampled like any other RGB emitter.
+        illuminant(color, wavelengths)
+    }
+}
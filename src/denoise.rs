@@ -0,0 +1,141 @@
+use anyhow::Context;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{camera::film_luminance, image::Image, rbg::Rgb};
+
+/// Edge-avoiding à-trous wavelet denoiser, as in SVGF (Schied et al. 2017)
+/// without the temporal part. Each pass blurs with a 5x5 kernel whose taps
+/// are twice as far apart as in the pass before, but neighbours only count
+/// as much as their albedo and normal resemble the pixel's own, and as their
+/// luminance does relative to the pixel's noise. Clean pixels so stay sharp,
+/// even where the albedo and normal are flat, like behind glass.
+pub struct Denoiser {
+    /// Blur passes; the kernel covers `4 * 2^iterations - 3` pixels across.
+    pub iterations: u32,
+    /// Luminance difference, in standard deviations of the pixel's noise, at
+    /// which neighbours start to count less.
+    pub luminance_sigma: f64,
+    /// Normal difference at which neighbours start to count less.
+    pub normal_sigma: f64,
+    /// Albedo difference at which neighbours start to count less.
+    pub albedo_sigma: f64,
+}
+
+/// B3 spline taps of the à-trous kernel.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+impl Denoiser {
+    pub fn new(iterations: u32) -> Self {
+        Self {
+            iterations,
+            luminance_sigma: 3.0,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+        }
+    }
+
+    /// Denoises the beauty pass of `img`, guided by its albedo, normal and
+    /// variance layers.
+    pub fn denoise(&self, img: &mut Image) -> anyhow::Result<()> {
+        let layer = |name: &str| -> anyhow::Result<Vec<Rgb>> {
+            let layer = img
+                .layer(name)
+                .with_context(|| format!("denoising needs the {name} layer"))?;
+            Ok(layer.iter().copied().collect())
+        };
+        let albedo = layer("albedo")?;
+        let normal = layer("normal")?;
+        let mut variance: Vec<f64> = layer("variance")?.iter().map(|v| v.r()).collect();
+        let mut color: Vec<Rgb> = img.iter().copied().collect();
+
+        for pass in 0..self.iterations {
+            let guides = Guides {
+                width: img.width,
+                height: img.height,
+                color: &color,
+                variance: &variance,
+                albedo: &albedo,
+                normal: &normal,
+            };
+            (color, variance) = (0..color.len())
+                .into_par_iter()
+                .map(|idx| self.filter_pixel(&guides, idx, 1 << pass))
+                .unzip();
+        }
+
+        img.as_mut_slice().copy_from_slice(&color);
+        Ok(())
+    }
+
+    /// One à-trous pass over pixel `idx` with taps `step` pixels apart,
+    /// returning its new colour and the variance of its luminance.
+    fn filter_pixel(&self, guides: &Guides, idx: usize, step: isize) -> (Rgb, f64) {
+        let luminance = film_luminance(guides.color[idx], false);
+        // Variances are blurred a little so a single noisy estimate doesn't
+        // decide
+        let pixel_variance = guides.blurred_variance(idx);
+
+        let mut sum = Rgb::BLACK;
+        let mut variance = 0.0;
+        let mut weights = 0.0;
+        for (n, h) in guides.taps(idx, step) {
+            // Differences are measured against the noise of both pixels, so
+            // dark pixels whose few samples all missed the light don't shut
+            // out their brighter neighbours and darken the image
+            let tolerance =
+                self.luminance_sigma * (pixel_variance + guides.blurred_variance(n)).sqrt() + 1e-6;
+            let edges = (film_luminance(guides.color[n], false) - luminance).abs() / tolerance
+                + (guides.normal[n] - guides.normal[idx]).len_sqrd()
+                    / (self.normal_sigma * self.normal_sigma)
+                + (guides.albedo[n] - guides.albedo[idx]).len_sqrd()
+                    / (self.albedo_sigma * self.albedo_sigma);
+            let weight = h * f64::exp(-edges);
+
+            sum = sum + guides.color[n] * weight;
+            variance += weight * weight * guides.variance[n];
+            weights += weight;
+        }
+
+        // The pixel itself always has weight
+        (sum / weights, variance / (weights * weights))
+    }
+}
+
+/// Buffers a denoising pass reads.
+struct Guides<'a> {
+    width: usize,
+    height: usize,
+    color: &'a [Rgb],
+    variance: &'a [f64],
+    albedo: &'a [Rgb],
+    normal: &'a [Rgb],
+}
+
+impl Guides<'_> {
+    /// Pixels of the 5x5 kernel around `idx` with taps `step` pixels apart
+    /// that lie in the image, with their kernel weights.
+    fn taps(&self, idx: usize, step: isize) -> impl Iterator<Item = (usize, f64)> + '_ {
+        let (i, j) = ((idx % self.width) as isize, (idx / self.width) as isize);
+
+        (0..25).filter_map(move |k| {
+            let x = i + (k % 5 - 2) * step;
+            let y = j + (k / 5 - 2) * step;
+            if !(0..self.width as isize).contains(&x) || !(0..self.height as isize).contains(&y) {
+                return None;
+            }
+
+            let h = KERNEL[(k % 5) as usize] * KERNEL[(k / 5) as usize];
+            Some((y as usize * self.width + x as usize, h))
+        })
+    }
+
+    /// Variance of pixel `idx` averaged with the pixels around it.
+    fn blurred_variance(&self, idx: usize) -> f64 {
+        let (sum, weights) = self
+            .taps(idx, 1)
+            .fold((0.0, 0.0), |(sum, weights), (n, h)| {
+                (sum + h * self.variance[n], weights + h)
+            });
+        sum / weights
+    }
+}
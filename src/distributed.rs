@@ -0,0 +1,365 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail, ensure};
+
+use crate::{
+    camera::{Camera, Job, JobResult, RenderObserver, progress_bar},
+    checkpoint::Checkpointed,
+    hittable::Hittable,
+    image::Image,
+};
+
+/// Sent by workers when they connect, so stray connections are turned away.
+const MAGIC: &[u8; 8] = b"RTWORK02";
+
+/// How often the coordinator looks for new workers and idle workers for
+/// jobs other workers gave back.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often workers tell the coordinator they're still rendering a job.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the coordinator waits to hear from a worker with a job before
+/// giving the job to another worker.
+const WORKER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest message a worker accepts. The coordinator only sends its command
+/// line and job areas; results are bounded by `Camera::max_result_size`.
+const MAX_SETTINGS_SIZE: u64 = 1 << 20;
+
+/// Messages between the coordinator and its workers, each sent as its
+/// length and then its `Checkpointed` bytes.
+enum Message {
+    /// The coordinator's command line and seed, to build the same scene and
+    /// camera from.
+    Settings {
+        args: Vec<String>,
+        seed: u64,
+    },
+    Job {
+        index: usize,
+        job: Job,
+    },
+    Result {
+        index: usize,
+        result: JobResult,
+    },
+    /// No jobs are left.
+    Done,
+    /// The worker is still rendering its job, see `HEARTBEAT_INTERVAL`.
+    Working,
+}
+
+impl Checkpointed for Message {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Settings { args, seed } => {
+                out.push(0);
+                args.save(out);
+                seed.save(out);
+            }
+            Message::Job { index, job } => {
+                out.push(1);
+                index.save(out);
+                job.save(out);
+            }
+            Message::Result { index, result } => {
+                out.push(2);
+                index.save(out);
+                result.save(out);
+            }
+            Message::Done => out.push(3),
+            Message::Working => out.push(4),
+        }
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        let (&tag, rest) = input.split_first().context("empty message")?;
+        *input = rest;
+        Ok(match tag {
+            0 => Message::Settings {
+                args: Vec::load(input)?,
+                seed: u64::load(input)?,
+            },
+            1 => Message::Job {
+                index: usize::load(input)?,
+                job: Job::load(input)?,
+            },
+            2 => Message::Result {
+                index: usize::load(input)?,
+                result: JobResult::load(input)?,
+            },
+            3 => Message::Done,
+            4 => Message::Working,
+            _ => bail!("unknown message {tag}"),
+        })
+    }
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> anyhow::Result<()> {
+    let mut bytes = Vec::new();
+    message.save(&mut bytes);
+    stream.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    stream.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Reads a message, turning it away before reading it if it's longer than
+/// `max_len` bytes.
+fn receive(stream: &mut TcpStream, max_len: u64) -> anyhow::Result<Message> {
+    let mut len = [0; 8];
+    stream.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    ensure!(
+        len <= max_len,
+        "a message of {len} bytes is larger than the {max_len} expected"
+    );
+
+    // The buffer only grows with what actually arrives
+    let mut bytes = Vec::new();
+    stream.take(len).read_to_end(&mut bytes)?;
+    ensure!(bytes.len() as u64 == len, "the message is truncated");
+    Message::load(&mut bytes.as_slice())
+}
+
+/// Renders `camera`'s image with the workers that connect to `address`,
+/// handing out `jobs` as they ask for them. Workers get `args` and the
+/// camera's seed to set up the same render with. Jobs of workers that fail
+/// go to the others, as do the jobs of workers that stay silent for
+/// `WORKER_TIMEOUT`. `observer` hears of the jobs as they're added to the
+/// image.
+pub fn coordinate(
+    camera: &Camera,
+    address: &str,
+    args: Vec<String>,
+    jobs: &[Job],
+    observer: &mut impl RenderObserver,
+) -> anyhow::Result<Image> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("Unable to listen for workers on {address}"))?;
+    listener.set_nonblocking(true)?;
+    eprintln!("Waiting for workers on {}", listener.local_addr()?);
+
+    let settings = Arc::new(Message::Settings {
+        args,
+        seed: camera.seed,
+    });
+    let shared_jobs: Arc<Vec<_>> = Arc::new(
+        jobs.iter()
+            .map(|job| (job.clone(), camera.max_result_size(job)))
+            .collect(),
+    );
+    let queue = Arc::new(Mutex::new((0..jobs.len()).collect::<VecDeque<_>>()));
+    let finished = Arc::new(AtomicBool::new(false));
+    let (results, received) = mpsc::channel();
+
+    let bar = progress_bar(jobs.iter().map(job_samples).sum());
+    let mut merger = camera.merger(jobs)?;
+    let mut workers = Vec::new();
+    let mut rendered = 0;
+    while !merger.done() && !camera.cancel.is_cancelled() {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let worker = Worker {
+                    stream,
+                    jobs: shared_jobs.clone(),
+                    settings: settings.clone(),
+                    queue: queue.clone(),
+                    finished: finished.clone(),
+                    results: results.clone(),
+                };
+                workers.push(thread::spawn(move || {
+                    if let Err(err) = worker.run() {
+                        eprintln!("Worker {peer} failed: {err:#}");
+                    }
+                }));
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err).context("Unable to accept a worker"),
+        }
+
+        match received.recv_timeout(POLL_INTERVAL) {
+            Ok((index, result)) => match merger.add(index, result, observer) {
+                Ok(()) => {
+                    bar.inc(job_samples(&jobs[index]));
+                    rendered += 1;
+                }
+                Err(err) => {
+                    eprintln!("Job {index} came back broken: {err:#}");
+                    queue.lock().unwrap().push_back(index);
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => unreachable!("a sender is kept"),
+        }
+    }
+
+    finished.store(true, Ordering::Relaxed);
+    bar.finish();
+    // Workers are told they're done once they've sent their last job, which
+    // they won't if the render was cancelled
+    let count = workers.len();
+    if merger.done() {
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }
+    eprintln!(
+        "Rendered {rendered} of {} jobs with {count} workers",
+        jobs.len()
+    );
+    Ok(merger.finish(observer))
+}
+
+fn job_samples(job: &Job) -> u64 {
+    (job.area.width * job.area.height) as u64 * job.samples.len() as u64
+}
+
+/// Coordinator side of a worker's connection.
+struct Worker {
+    stream: TcpStream,
+    /// Every job, with the most bytes its result may take.
+    jobs: Arc<Vec<(Job, u64)>>,
+    settings: Arc<Message>,
+    queue: Arc<Mutex<VecDeque<usize>>>,
+    finished: Arc<AtomicBool>,
+    results: Sender<(usize, JobResult)>,
+}
+
+impl Worker {
+    fn run(mut self) -> anyhow::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        self.stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut magic = [0; 8];
+        self.stream.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "not a render worker");
+        // Workers send heartbeats while they render, so silence means
+        // they're gone
+        self.stream.set_read_timeout(Some(WORKER_TIMEOUT))?;
+        self.stream.set_write_timeout(Some(WORKER_TIMEOUT))?;
+        send(&mut self.stream, &self.settings)?;
+
+        loop {
+            if self.finished.load(Ordering::Relaxed) {
+                return send(&mut self.stream, &Message::Done);
+            }
+            let Some(index) = self.queue.lock().unwrap().pop_front() else {
+                // Another worker may still give a job back
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+
+            match self.render(index) {
+                Ok(result) => {
+                    // The coordinator stops listening once it's finished
+                    let _ = self.results.send((index, result));
+                }
+                Err(err) => {
+                    self.queue.lock().unwrap().push_front(index);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    fn render(&mut self, index: usize) -> anyhow::Result<JobResult> {
+        let (job, max_len) = self.jobs[index].clone();
+        send(&mut self.stream, &Message::Job { index, job })?;
+
+        loop {
+            let message = receive(&mut self.stream, max_len).map_err(|err| {
+                match err
+                    .downcast_ref::<std::io::Error>()
+                    .map(std::io::Error::kind)
+                {
+                    // Workers send `Working` well within the timeout while they render
+                    Some(std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                        err.context(format!("heard nothing for {WORKER_TIMEOUT:?}"))
+                    }
+                    _ => err.context("lost the connection"),
+                }
+            })?;
+            match message {
+                Message::Working => {}
+                Message::Result {
+                    index: done,
+                    result,
+                } if done == index => return Ok(result),
+                _ => bail!("the worker didn't send back job {index}"),
+            }
+        }
+    }
+}
+
+/// Connects to the coordinator at `address` and renders the jobs it hands
+/// out until there are none left. `setup` builds the scene and camera from
+/// the coordinator's command line and seed.
+pub fn work<W: Hittable>(
+    address: &str,
+    setup: impl FnOnce(Vec<String>, u64) -> anyhow::Result<(Camera, W)>,
+) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(address)
+        .with_context(|| format!("Unable to connect to the coordinator at {address}"))?;
+    stream.write_all(MAGIC)?;
+
+    let Message::Settings { args, seed } = receive(&mut stream, MAX_SETTINGS_SIZE)? else {
+        bail!("the coordinator didn't send its settings");
+    };
+    let (camera, world) = setup(args, seed)?;
+    eprintln!("Rendering for {address}");
+
+    let start = Instant::now();
+    let mut done = 0;
+    loop {
+        match receive(&mut stream, MAX_SETTINGS_SIZE).context("lost the coordinator")? {
+            Message::Job { index, job } => {
+                let result =
+                    render_with_heartbeats(&mut stream, || camera.render_job(&world, &job))?;
+                send(&mut stream, &Message::Result { index, result })
+                    .context("lost the coordinator")?;
+                done += 1;
+            }
+            Message::Done => break,
+            _ => bail!("unexpected message from the coordinator"),
+        }
+    }
+
+    eprintln!("Rendered {done} jobs in {:.1?}", start.elapsed());
+    Ok(())
+}
+
+/// Runs `render` while telling the coordinator every `HEARTBEAT_INTERVAL`
+/// that the job is still being worked on.
+fn render_with_heartbeats(
+    stream: &mut TcpStream,
+    render: impl FnOnce() -> JobResult + Send,
+) -> anyhow::Result<JobResult> {
+    let (sender, rendered) = mpsc::channel();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let _ = sender.send(render());
+        });
+        loop {
+            match rendered.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(result) => return Ok(result),
+                Err(RecvTimeoutError::Timeout) => {
+                    send(stream, &Message::Working).context("lost the coordinator")?
+                }
+                Err(RecvTimeoutError::Disconnected) => bail!("the render panicked"),
+            }
+        }
+    })
+}
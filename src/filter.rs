@@ -0,0 +1,100 @@
+use std::f64::consts::PI;
+
+use clap::ValueEnum;
+
+/// Shape of a `Filter`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum FilterKind {
+    /// Every sample counts fully towards the pixel it's in, and nothing else
+    Box,
+    /// Weight falling linearly to zero at the radius
+    Tent,
+    /// Gaussian with a standard deviation of half a pixel, shifted to reach
+    /// zero at the radius
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, sharp with mild ringing
+    Mitchell,
+    /// Sinc windowed by a wider sinc, sharpest but rings the most
+    Lanczos,
+    /// Blackman-Harris window, smooth with almost no ringing
+    BlackmanHarris,
+}
+
+/// Pixel reconstruction filter. Samples add to every pixel whose centre is
+/// within `radius` pixels along both axes, weighted by the separable filter.
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Filter {
+    /// A filter of the given kind with its usual radius.
+    pub fn new(kind: FilterKind) -> Self {
+        let radius = match kind {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::Lanczos | FilterKind::BlackmanHarris => 2.0,
+        };
+
+        Self { kind, radius }
+    }
+
+    /// Weight of a sample `(dx, dy)` pixels away from a pixel centre.
+    pub fn eval(&self, dx: f64, dy: f64) -> f64 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        // Half-open, so a sample on a pixel edge counts towards one pixel only
+        if self.kind == FilterKind::Box {
+            return if (-r..r).contains(&x) { 1.0 } else { 0.0 };
+        }
+
+        let x = x.abs();
+        if x >= r {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                let gaussian = |x: f64| f64::exp(-2.0 * x * x);
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / r),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+            FilterKind::BlackmanHarris => {
+                let t = 2.0 * PI * (x + r) / (2.0 * r);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    f64::sin(PI * x) / (PI * x)
+}
+
+/// Mitchell-Netravali cubic for B = C = 1/3, on `[0, 2)`.
+fn mitchell(x: f64) -> f64 {
+    let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    } else {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    }
+}
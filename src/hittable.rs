@@ -1,10 +1,12 @@
 use std::ops::{self};
 
+use rand::{Rng, RngCore};
+
 use crate::{
     material::Material,
     point::Point3,
     ray::Ray,
-    vec3::{Vec3, dot},
+    vec3::{Vec3, dot, rand_unit_vec},
 };
 
 #[derive(Clone)]
@@ -12,24 +14,73 @@ pub struct HitRecord {
     pub p: Point3,
     pub n: Vec3,
     pub t: f64,
+    /// Surface parameterisation at the hit, both in `[0, 1]`.
+    pub uv: (f64, f64),
     pub front_face: bool,
     pub mat: Material,
+    /// Index of the top-level object that was hit, set by `Hittables`.
+    pub object_id: usize,
 }
 
 impl HitRecord {
-    pub fn new(p: Point3, n: Vec3, t: f64, front_face: bool, mat: Material) -> Self {
+    pub fn new(
+        p: Point3,
+        n: Vec3,
+        t: f64,
+        uv: (f64, f64),
+        front_face: bool,
+        mat: Material,
+    ) -> Self {
         Self {
             p,
             n,
             t,
+            uv,
             front_face,
-            mat: mat,
+            mat,
+            object_id: 0,
         }
     }
 }
 
+/// A point picked on the surface of an emissive object.
+pub struct EmitterSample {
+    pub p: Point3,
+    /// Outward surface normal.
+    pub n: Vec3,
+    pub mat: Material,
+    /// Area density of picking `p`, including the choice of object.
+    pub pdf: f64,
+}
+
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, ray_range: ops::Range<f64>) -> Option<HitRecord>;
+
+    /// Fraction of light getting through along `ray` within `ray_range`, for
+    /// occlusion rays. Surfaces block everything; media let part of it through.
+    fn transmittance(&self, ray: &Ray, ray_range: ops::Range<f64>) -> f64 {
+        if self.hit(ray, ray_range).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    /// Whether this is an area light that `sample_emitter` can pick points on.
+    fn is_emitter(&self) -> bool {
+        false
+    }
+
+    /// Picks a point on an emissive surface, for paths that start at a light.
+    fn sample_emitter(&self, _rng: &mut dyn RngCore) -> Option<EmitterSample> {
+        None
+    }
+
+    /// Area density with which `sample_emitter` picks `p` on the object with
+    /// the given `HitRecord::object_id`.
+    fn emitter_pdf(&self, _object_id: usize, _p: Point3) -> f64 {
+        0.0
+    }
 }
 
 pub struct Sphere {
@@ -43,7 +94,7 @@ impl Sphere {
         Self {
             center,
             radius,
-            mat: mat,
+            mat,
         }
     }
 }
@@ -67,10 +118,19 @@ impl Hittable for Sphere {
                 let front_face = dot(*ray.direction(), out_normal) < 0.0;
                 let normal = if front_face { out_normal } else { -out_normal };
 
+                // Longitude around +y, latitude from -y to +y
+                let theta = f64::acos(-out_normal.y);
+                let phi = f64::atan2(-out_normal.z, out_normal.x) + std::f64::consts::PI;
+                let uv = (
+                    phi / (2.0 * std::f64::consts::PI),
+                    theta / std::f64::consts::PI,
+                );
+
                 Some(HitRecord::new(
                     ray.at(root),
                     normal,
                     root,
+                    uv,
                     front_face,
                     self.mat,
                 ))
@@ -83,20 +143,52 @@ impl Hittable for Sphere {
 
         check_root((h - sqrtd) / a).or_else(|| check_root((h + sqrtd) / a))
     }
+
+    fn is_emitter(&self) -> bool {
+        matches!(self.mat, Material::DiffuseLight { .. })
+    }
+
+    fn sample_emitter(&self, mut rng: &mut dyn RngCore) -> Option<EmitterSample> {
+        if !self.is_emitter() {
+            return None;
+        }
+
+        let n = rand_unit_vec(&mut rng);
+        Some(EmitterSample {
+            p: self.center + self.radius * n,
+            n,
+            mat: self.mat,
+            pdf: self.emitter_pdf(0, self.center),
+        })
+    }
+
+    fn emitter_pdf(&self, _object_id: usize, _p: Point3) -> f64 {
+        if self.is_emitter() {
+            1.0 / (4.0 * std::f64::consts::PI * self.radius * self.radius)
+        } else {
+            0.0
+        }
+    }
 }
 
 pub struct Hittables {
     objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    /// Indices of the objects that are area lights.
+    emitters: Vec<usize>,
 }
 
 impl Hittables {
     pub fn new() -> Self {
         Self {
             objects: Vec::new(),
+            emitters: Vec::new(),
         }
     }
 
     pub fn add(&mut self, object: impl Hittable + 'static) {
+        if object.is_emitter() {
+            self.emitters.push(self.objects.len());
+        }
         self.objects.push(Box::new(object));
     }
 }
@@ -106,15 +198,53 @@ impl Hittable for Hittables {
         let mut closest_so_far = ray_range.end;
         let mut temp_hit: Option<HitRecord> = None;
 
-        for object in self.objects.iter() {
+        for (id, object) in self.objects.iter().enumerate() {
             let closest_range = ray_range.start..closest_so_far;
 
-            if let Some(hit) = object.hit(ray, closest_range) {
+            if let Some(mut hit) = object.hit(ray, closest_range) {
                 closest_so_far = hit.t;
+                hit.object_id = id;
                 temp_hit = Some(hit);
             }
         }
 
         temp_hit
     }
+
+    fn transmittance(&self, ray: &Ray, ray_range: ops::Range<f64>) -> f64 {
+        let mut transmittance = 1.0;
+
+        for object in self.objects.iter() {
+            transmittance *= object.transmittance(ray, ray_range.clone());
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+        }
+
+        transmittance
+    }
+
+    fn is_emitter(&self) -> bool {
+        !self.emitters.is_empty()
+    }
+
+    /// Picks one of the top-level lights uniformly, then a point on it.
+    fn sample_emitter(&self, rng: &mut dyn RngCore) -> Option<EmitterSample> {
+        if self.emitters.is_empty() {
+            return None;
+        }
+
+        let id = self.emitters[rng.random_range(0..self.emitters.len())];
+        let mut sample = self.objects[id].sample_emitter(rng)?;
+        sample.pdf /= self.emitters.len() as f64;
+        Some(sample)
+    }
+
+    fn emitter_pdf(&self, object_id: usize, p: Point3) -> f64 {
+        if !self.emitters.contains(&object_id) {
+            return 0.0;
+        }
+
+        self.objects[object_id].emitter_pdf(object_id, p) / self.emitters.len() as f64
+    }
 }
@@ -1,11 +1,30 @@
-use crate::rbg::Rgb;
 use std::ops::{Index, IndexMut};
 
+use anyhow::ensure;
+
+use crate::{checkpoint::Checkpointed, rbg::Rgb, tile::Tile};
+
+/// An auxiliary buffer stored alongside the beauty pass, e.g. albedo or normals.
+#[derive(Debug)]
+pub struct Layer {
+    pub name: String,
+    data: Vec<Rgb>,
+}
+
+impl Layer {
+    pub fn iter(&self) -> impl Iterator<Item = &Rgb> {
+        self.data.iter()
+    }
+}
+
 #[derive(Debug)]
 pub struct Image {
     pub width: usize,
     pub height: usize,
     data: Vec<Rgb>,
+    /// Filter weight summed into each pixel by `add_weighted`.
+    weights: Vec<f64>,
+    layers: Vec<Layer>,
 }
 
 impl Image {
@@ -14,6 +33,8 @@ impl Image {
             width,
             height,
             data: vec![Rgb::BLACK; width * height],
+            weights: vec![0.0; width * height],
+            layers: Vec::new(),
         }
     }
 
@@ -28,6 +49,96 @@ impl Image {
     pub fn as_mut_slice(&mut self) -> &mut [Rgb] {
         &mut self.data
     }
+
+    /// Adds samples already multiplied by their reconstruction filter
+    /// `weight` to pixel `idx`.
+    pub fn add_weighted(&mut self, idx: usize, weighted: Rgb, weight: f64) {
+        self.data[idx] = self.data[idx] + weighted;
+        self.weights[idx] += weight;
+    }
+
+    /// Weighted average of the samples `add_weighted` added to pixel `idx`,
+    /// black if there are none.
+    pub fn weighted_average(&self, idx: usize) -> Rgb {
+        if self.weights[idx] != 0.0 {
+            self.data[idx] / self.weights[idx]
+        } else {
+            Rgb::BLACK
+        }
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = &Layer> {
+        self.layers.iter()
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+
+    /// Pixels of the named layer, which is created black if it doesn't exist yet.
+    pub fn layer_mut(&mut self, name: &str) -> &mut [Rgb] {
+        let idx = match self.layers.iter().position(|l| l.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.layers.push(Layer {
+                    name: name.to_string(),
+                    data: vec![Rgb::BLACK; self.width * self.height],
+                });
+                self.layers.len() - 1
+            }
+        };
+
+        &mut self.layers[idx].data
+    }
+
+    /// Adds a layer named `name` holding `data`, one value per pixel.
+    pub fn add_layer(&mut self, name: impl Into<String>, data: Vec<Rgb>) {
+        self.layers.push(Layer {
+            name: name.into(),
+            data,
+        });
+    }
+
+    /// The pixels of `rect`, with its layers.
+    pub fn crop(&self, rect: &Tile) -> Image {
+        let mut img = Image::new(rect.width, rect.height);
+        let pixels: Vec<usize> = rect.pixels(self.width).collect();
+
+        for (out, &idx) in img.data.iter_mut().zip(&pixels) {
+            *out = self.data[idx];
+        }
+        for layer in &self.layers {
+            let data = pixels.iter().map(|&idx| layer.data[idx]).collect();
+            img.layers.push(Layer {
+                name: layer.name.clone(),
+                data,
+            });
+        }
+
+        img
+    }
+
+    /// Copies `other` and its layers over the pixels from `(x, y)` on. Layers
+    /// `other` doesn't have are left as they are.
+    pub fn paste(&mut self, other: &Image, x: usize, y: usize) {
+        let rect = Tile {
+            x,
+            y,
+            width: other.width,
+            height: other.height,
+        };
+        let pixels: Vec<usize> = rect.pixels(self.width).collect();
+
+        for (&idx, value) in pixels.iter().zip(&other.data) {
+            self.data[idx] = *value;
+        }
+        for layer in &other.layers {
+            let data = self.layer_mut(&layer.name);
+            for (&idx, value) in pixels.iter().zip(&layer.data) {
+                data[idx] = *value;
+            }
+        }
+    }
 }
 
 impl Index<(usize, usize)> for Image {
@@ -44,3 +155,60 @@ impl IndexMut<(usize, usize)> for Image {
         &mut self.data[idx]
     }
 }
+
+/// Saves the pixels and their filter weights, not the layers.
+impl Checkpointed for Image {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.width.save(out);
+        self.height.save(out);
+        self.data.save(out);
+        self.weights.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        let (width, height) = (usize::load(input)?, usize::load(input)?);
+        let data = Vec::load(input)?;
+        let weights = Vec::load(input)?;
+        ensure!(
+            data.len() == width * height && weights.len() == width * height,
+            "the checkpoint's image doesn't match its size"
+        );
+
+        Ok(Self {
+            width,
+            height,
+            data,
+            weights,
+            layers: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::tests::round_trip;
+
+    #[test]
+    fn image_round_trips_without_layers() {
+        let mut img = Image::new(3, 2);
+        img.add_weighted(0, Rgb::new(0.5, 1.0, 2.0), 0.25);
+        img.add_weighted(5, Rgb::new(-1.0, 0.0, 3.0), 1.5);
+        img.layer_mut("albedo")[1] = Rgb::new(1.0, 1.0, 1.0);
+
+        let loaded = round_trip(&img);
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.weighted_average(0), img.weighted_average(0));
+        assert_eq!(loaded.weighted_average(5), img.weighted_average(5));
+        assert!(loaded.layers().next().is_none());
+    }
+
+    #[test]
+    fn image_with_the_wrong_size_is_an_error() {
+        let mut bytes = Vec::new();
+        Image::new(3, 2).save(&mut bytes);
+        // Claim a width of 4
+        bytes[..8].copy_from_slice(&4u64.to_le_bytes());
+        assert!(Image::load(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Context, ensure};
+
+use crate::{image::Image, rbg::Rgb};
+
+/// Reads an image as `ExrFileWriter` or `PpmFileWriter` write them, by the
+/// extension of `path`.
+pub fn read_image(path: &Path) -> anyhow::Result<Image> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Unable to read the image at : {}", path.to_string_lossy()))?;
+
+    if path.extension().is_some_and(|ext| ext == "exr") {
+        read_exr(&bytes).context("Failed while reading EXR image data")
+    } else {
+        read_ppm(&bytes).context("Failed while reading PPM image data")
+    }
+}
+
+/// Reads plain PPM files, undoing the gamma `PpmWriter` applies.
+fn read_ppm(bytes: &[u8]) -> anyhow::Result<Image> {
+    let text = std::str::from_utf8(bytes).context("Only plain (P3) PPM files can be read")?;
+    let mut tokens = text.split_ascii_whitespace();
+    ensure!(
+        tokens.next() == Some("P3"),
+        "Only plain (P3) PPM files can be read"
+    );
+
+    let mut number = || -> anyhow::Result<usize> {
+        Ok(tokens
+            .next()
+            .context("The PPM file is truncated")?
+            .parse()?)
+    };
+    let (width, height, max) = (number()?, number()?, number()?);
+    ensure!(max > 0, "The PPM file has a maximum value of 0");
+
+    let mut img = Image::new(width, height);
+    for pixel in img.as_mut_slice() {
+        let mut channel = || -> anyhow::Result<f64> { Ok((number()? as f64 / max as f64).powi(2)) };
+        *pixel = Rgb::new(channel()?, channel()?, channel()?);
+    }
+
+    Ok(img)
+}
+
+/// Reads uncompressed, single-part scanline OpenEXR files with 32-bit float
+/// channels, like `ExrWriter` writes. `R`, `G`, `B` become the beauty pass and
+/// `<layer>.R`, `<layer>.G`, `<layer>.B` image layers; other channels are
+/// skipped.
+fn read_exr(bytes: &[u8]) -> anyhow::Result<Image> {
+    let mut input = bytes;
+    ensure!(
+        take(&mut input, 4)? == [0x76, 0x2f, 0x31, 0x01],
+        "Not an OpenEXR file"
+    );
+    // Long attribute names are the only flag that doesn't change the layout
+    let version = take(&mut input, 4)?;
+    ensure!(
+        version[0] == 2 && version[1] & !0x04 == 0,
+        "Only single-part scanline EXR files can be read"
+    );
+
+    let mut channels = Vec::new();
+    let mut window = None;
+    loop {
+        let attribute = name(&mut input)?;
+        if attribute.is_empty() {
+            break;
+        }
+        let _kind = name(&mut input)?;
+        let size = i32::from_le_bytes(take(&mut input, 4)?.try_into()?);
+        let mut value = take(&mut input, size.try_into()?)?;
+
+        match attribute {
+            "channels" => loop {
+                let channel = name(&mut value)?;
+                if channel.is_empty() {
+                    break;
+                }
+                let info = take(&mut value, 16)?;
+                ensure!(
+                    info[..4] == 2i32.to_le_bytes(),
+                    "Only 32-bit float EXR channels can be read"
+                );
+                ensure!(
+                    info[8..] == [1, 0, 0, 0, 1, 0, 0, 0],
+                    "Only EXR channels without subsampling can be read"
+                );
+                channels.push(channel);
+            },
+            "compression" => ensure!(value == [0], "Only uncompressed EXR files can be read"),
+            "dataWindow" => {
+                let mut corners = [0; 4];
+                for corner in &mut corners {
+                    *corner = i32::from_le_bytes(take(&mut value, 4)?.try_into()?);
+                }
+                window = Some(corners);
+            }
+            _ => {}
+        }
+    }
+
+    let [x_min, y_min, x_max, y_max] = window.context("The EXR file has no data window")?;
+    let width = usize::try_from(x_max - x_min + 1)?;
+    let height = usize::try_from(y_max - y_min + 1)?;
+
+    // Components of the beauty pass (named "") and of every layer
+    let mut targets: BTreeMap<&str, [Vec<f64>; 3]> = BTreeMap::new();
+    for channel in &channels {
+        let (target, _) = channel.rsplit_once('.').unwrap_or(("", channel));
+        targets
+            .entry(target)
+            .or_insert_with(|| std::array::from_fn(|_| vec![0.0; width * height]));
+    }
+
+    let mut offsets = take(&mut input, height * 8)?;
+    for _ in 0..height {
+        let offset = u64::from_le_bytes(take(&mut offsets, 8)?.try_into()?);
+        let mut block = bytes
+            .get(usize::try_from(offset)?..)
+            .context("The EXR file is truncated")?;
+
+        let y = i32::from_le_bytes(take(&mut block, 4)?.try_into()?) - y_min;
+        let y = usize::try_from(y).ok().filter(|&y| y < height);
+        let y = y.context("The EXR file has a scanline outside its data window")?;
+        take(&mut block, 4)?;
+
+        for channel in &channels {
+            let line = take(&mut block, width * 4)?;
+            let (target, component) = channel.rsplit_once('.').unwrap_or(("", channel));
+            let Some(component) = ["R", "G", "B"].iter().position(|c| *c == component) else {
+                continue;
+            };
+
+            let values = &mut targets.get_mut(target).expect("target of every channel")[component]
+                [y * width..(y + 1) * width];
+            for (value, bytes) in values.iter_mut().zip(line.chunks_exact(4)) {
+                *value = f32::from_le_bytes(bytes.try_into()?) as f64;
+            }
+        }
+    }
+
+    let mut img = Image::new(width, height);
+    for (target, [r, g, b]) in targets {
+        let pixels = if target.is_empty() {
+            img.as_mut_slice()
+        } else {
+            img.layer_mut(target)
+        };
+        for (idx, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = Rgb::new(r[idx], g[idx], b[idx]);
+        }
+    }
+
+    Ok(img)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> anyhow::Result<&'a [u8]> {
+    ensure!(input.len() >= len, "The EXR file is truncated");
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+/// Null terminated name of an EXR attribute, type or channel.
+fn name<'a>(input: &mut &'a [u8]) -> anyhow::Result<&'a str> {
+    let len = input
+        .iter()
+        .position(|&b| b == 0)
+        .context("The EXR file is truncated")?;
+    let name = std::str::from_utf8(&input[..len])?;
+    *input = &input[len + 1..];
+    Ok(name)
+}
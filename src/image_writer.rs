@@ -33,7 +33,7 @@ impl<W: std::io::Write> PpmWriter<W> {
         for p in image.iter() {
             let (r, g, b) = serialize_pixel(p);
             writeln!(&mut self.writer, "{} {} {} ", r, g, b)
-                .with_context(|| format!("An I/O error occured while writing pixel data"))?;
+                .context("An I/O error occured while writing pixel data")?;
         }
 
         Ok(())
@@ -69,3 +69,285 @@ impl ImageWriter for PpmFileWriter {
         self.implementation.write(image)
     }
 }
+
+/// Writes 8-bit RGB PNG files, with the same gamma as `PpmWriter`. The image
+/// data is stored without compression, which keeps encoding cheap enough to
+/// do over and over for a live preview.
+pub struct PngWriter<W: std::io::Write> {
+    writer: W,
+}
+
+impl<W: std::io::Write> PngWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    fn chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+        let mut crc = Crc32::new();
+        crc.update(kind);
+        crc.update(data);
+
+        self.writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        self.writer.write_all(kind)?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&crc.finish().to_be_bytes())
+    }
+
+    fn write_impl(&mut self, image: &Image) -> anyhow::Result<()> {
+        let linear_to_gamma = |f: f64| -> f64 { if f > 0.0 { f64::sqrt(f) } else { f } };
+        let conv = |f: f64| ((linear_to_gamma(f)).clamp(0.0, 1.0) * 255.99) as u8;
+
+        // Every scanline starts with its filter type, none
+        let mut raw = Vec::with_capacity(image.height * (1 + 3 * image.width));
+        let pixels: Vec<&Rgb> = image.iter().collect();
+        for row in pixels.chunks(image.width.max(1)) {
+            raw.push(0);
+            for px in row {
+                raw.extend_from_slice(&[conv(px.r()), conv(px.g()), conv(px.b())]);
+            }
+        }
+
+        // A zlib stream of stored deflate blocks
+        let mut idat = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = raw.chunks(u16::MAX as usize).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            idat.push(u8::from(i + 1 == blocks.len()));
+            idat.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            idat.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+            idat.extend_from_slice(block);
+        }
+        if blocks.is_empty() {
+            idat.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+        }
+        idat.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&(image.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(image.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, not interlaced
+
+        self.writer
+            .write_all(b"\x89PNG\r\n\x1a\n")
+            .and_then(|_| self.chunk(b"IHDR", &ihdr))
+            .and_then(|_| self.chunk(b"IDAT", &idat))
+            .and_then(|_| self.chunk(b"IEND", &[]))
+            .context("An I/O error occurred while writing PNG data")
+    }
+}
+
+impl<W: std::io::Write> ImageWriter for PngWriter<W> {
+    fn write(&mut self, image: &Image) -> anyhow::Result<()> {
+        self.write_impl(image)
+            .context("Failed while writing PNG image data")
+    }
+}
+
+/// CRC-32 as PNG chunks are checked with.
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        let table = std::array::from_fn(|n| {
+            (0..8).fold(n as u32, |c, _| {
+                if c & 1 != 0 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            })
+        });
+        Self {
+            table,
+            crc: 0xffffffff,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.crc = self.table[((self.crc ^ byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
+
+/// Adler-32 checksum ending a zlib stream.
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+pub struct PngFileWriter {
+    implementation: PngWriter<BufWriter<File>>,
+}
+
+impl PngFileWriter {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let file_handle = File::create(path).with_context(|| {
+            format!(
+                "Unable to create or overwrite the output file at : {}",
+                path.to_string_lossy()
+            )
+        })?;
+
+        Ok(Self {
+            implementation: PngWriter::new(BufWriter::new(file_handle)),
+        })
+    }
+}
+
+impl ImageWriter for PngFileWriter {
+    fn write(&mut self, image: &Image) -> anyhow::Result<()> {
+        self.implementation.write(image)
+    }
+}
+
+/// Writes uncompressed, single-part scanline OpenEXR files with 32-bit float
+/// channels. The beauty pass becomes `R`, `G`, `B` and every image layer
+/// `<layer>.R`, `<layer>.G`, `<layer>.B`. Values are stored linear.
+pub struct ExrWriter<W: std::io::Write> {
+    writer: W,
+}
+
+impl<W: std::io::Write> ExrWriter<W> {
+    fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    }
+
+    fn write_impl(&mut self, image: &Image) -> anyhow::Result<()> {
+        // (channel name, pixel source, component) sorted by name, as EXR requires.
+        let mut sources = vec![(String::new(), image.iter().collect::<Vec<_>>())];
+        for layer in image.layers() {
+            sources.push((format!("{}.", layer.name), layer.iter().collect()));
+        }
+
+        let mut channels: Vec<(String, &[&Rgb], usize)> = Vec::new();
+        for (prefix, pixels) in &sources {
+            for (component, name) in ["R", "G", "B"].iter().enumerate() {
+                channels.push((format!("{prefix}{name}"), pixels, component));
+            }
+        }
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut chlist = Vec::new();
+        for (name, _, _) in &channels {
+            chlist.extend_from_slice(name.as_bytes());
+            chlist.push(0);
+            chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+            chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+            chlist.extend_from_slice(&1i32.to_le_bytes()); // xSampling
+            chlist.extend_from_slice(&1i32.to_le_bytes()); // ySampling
+        }
+        chlist.push(0);
+
+        let mut window = Vec::new();
+        for v in [0, 0, image.width as i32 - 1, image.height as i32 - 1] {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+        Self::attribute(&mut header, "channels", "chlist", &chlist);
+        Self::attribute(&mut header, "compression", "compression", &[0]);
+        Self::attribute(&mut header, "dataWindow", "box2i", &window);
+        Self::attribute(&mut header, "displayWindow", "box2i", &window);
+        Self::attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        Self::attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1.0f32.to_le_bytes(),
+        );
+        Self::attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        Self::attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1.0f32.to_le_bytes(),
+        );
+        header.push(0);
+
+        self.writer
+            .write_all(&header)
+            .context("An I/O error occurred while writing the EXR header")?;
+
+        // One scanline per block: line number, data size, then each channel in turn.
+        let line_size = channels.len() * image.width * 4;
+        let block_size = 8 + line_size;
+        let table_end = header.len() + image.height * 8;
+
+        let mut offsets = Vec::with_capacity(image.height * 8);
+        for y in 0..image.height {
+            offsets.extend_from_slice(&((table_end + y * block_size) as u64).to_le_bytes());
+        }
+        self.writer
+            .write_all(&offsets)
+            .context("An I/O error occurred while writing the EXR offset table")?;
+
+        let mut block = Vec::with_capacity(block_size);
+        for y in 0..image.height {
+            block.clear();
+            block.extend_from_slice(&(y as i32).to_le_bytes());
+            block.extend_from_slice(&(line_size as i32).to_le_bytes());
+
+            for (_, pixels, component) in &channels {
+                for px in &pixels[y * image.width..(y + 1) * image.width] {
+                    block.extend_from_slice(&(px[*component] as f32).to_le_bytes());
+                }
+            }
+
+            self.writer
+                .write_all(&block)
+                .context("An I/O error occured while writing pixel data")?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, image: &Image) -> anyhow::Result<()> {
+        self.write_impl(image)
+            .context("Failed while writing EXR image data")
+    }
+}
+
+pub struct ExrFileWriter {
+    implementation: ExrWriter<BufWriter<File>>,
+}
+
+impl ExrFileWriter {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let file_handle = File::create(path).with_context(|| {
+            format!(
+                "Unable to create or overwrite the output file at : {}",
+                path.to_string_lossy()
+            )
+        })?;
+
+        Ok(Self {
+            implementation: ExrWriter::new(BufWriter::new(file_handle)),
+        })
+    }
+}
+
+impl ImageWriter for ExrFileWriter {
+    fn write(&mut self, image: &Image) -> anyhow::Result<()> {
+        self.implementation.write(image)
+    }
+}
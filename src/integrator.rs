@@ -0,0 +1,338 @@
+use rand::{Rng, RngCore};
+
+use crate::{
+    camera::{Camera, FilmProgress, Viewport},
+    checkpoint::Checkpointed,
+    hittable::{HitRecord, Hittable},
+    ray::Ray,
+    rbg::Rgb,
+    rgb_to_spectrum::illuminant,
+    vec3::{Vec3, lerp, norm},
+};
+
+/// What rays that escape the scene see.
+#[derive(Clone, Copy)]
+pub enum Background {
+    /// White-to-blue gradient
+    Sky,
+    Solid(Rgb),
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Rgb {
+        let color = match self {
+            Self::Sky => {
+                let u = norm(*ray.direction());
+                let blend = 0.9 * (u.y + 1.0);
+
+                lerp(&Rgb::new(1.0, 1.0, 1.0), &Rgb::new(0.5, 0.7, 1.0), blend)
+            }
+            Self::Solid(color) => *color,
+        };
+
+        illuminant(color, ray.wavelengths())
+    }
+}
+
+/// Where a radiance contribution was emitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LightId {
+    Background,
+    /// An emissive top-level object, by `HitRecord::object_id`.
+    Object(usize),
+}
+
+/// Auxiliary outputs recorded alongside the beauty pass.
+///
+/// `emission`, `direct`, `indirect` and `lights` are radiance and so are in
+/// the same units as the beauty pass; each set of them sums to it.
+#[derive(Clone, Debug)]
+pub struct Aovs {
+    pub albedo: Rgb,
+    pub normal: Vec3,
+    /// Distance to the first hit, infinite for misses.
+    pub depth: f64,
+    /// Light emitted by whatever the camera sees directly.
+    pub emission: Rgb,
+    /// Light reaching the camera after one bounce.
+    pub direct: Rgb,
+    /// Light reaching the camera after two or more bounces.
+    pub indirect: Rgb,
+    pub lights: Vec<(LightId, Rgb)>,
+    /// Unoccluded fraction of the first hit's hemisphere, see `AmbientOcclusion`.
+    pub occlusion: f64,
+}
+
+impl Aovs {
+    pub fn new() -> Self {
+        Self {
+            albedo: Rgb::BLACK,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            depth: f64::INFINITY,
+            emission: Rgb::BLACK,
+            direct: Rgb::BLACK,
+            indirect: Rgb::BLACK,
+            lights: Vec::new(),
+            occlusion: 0.0,
+        }
+    }
+
+    /// Records the first-hit surface data for a camera ray.
+    pub fn record_surface(&mut self, ray: &Ray, hit: Option<&HitRecord>) {
+        if let Some(h) = hit {
+            self.albedo = h.mat.albedo();
+            self.normal = h.n;
+            self.depth = h.t * ray.direction().len();
+        }
+    }
+
+    /// Records radiance emitted by `light` reaching the camera after `bounces` bounces.
+    pub fn record_light(&mut self, bounces: i32, light: LightId, radiance: Rgb) {
+        match bounces {
+            0 => self.emission = self.emission + radiance,
+            1 => self.direct = self.direct + radiance,
+            _ => self.indirect = self.indirect + radiance,
+        }
+
+        match self.lights.iter_mut().find(|(id, _)| *id == light) {
+            Some((_, sum)) => *sum = *sum + radiance,
+            None => self.lights.push((light, radiance)),
+        }
+    }
+
+    /// Adds one sample's outputs, passing radiance values through `to_film`.
+    /// The depth keeps the closest hit.
+    pub fn accumulate(&mut self, sample: &Aovs, to_film: impl Fn(Rgb) -> Rgb) {
+        self.albedo = self.albedo + sample.albedo;
+        self.normal = self.normal + sample.normal;
+        self.depth = self.depth.min(sample.depth);
+        self.emission = self.emission + to_film(sample.emission);
+        self.direct = self.direct + to_film(sample.direct);
+        self.indirect = self.indirect + to_film(sample.indirect);
+        self.occlusion += sample.occlusion;
+
+        for (light, radiance) in &sample.lights {
+            let radiance = to_film(*radiance);
+            match self.lights.iter_mut().find(|(id, _)| id == light) {
+                Some((_, sum)) => *sum = *sum + radiance,
+                None => self.lights.push((*light, radiance)),
+            }
+        }
+    }
+
+    /// Scales everything but the depth, e.g. to average accumulated samples.
+    pub fn scale(&mut self, factor: f64) {
+        self.albedo = self.albedo * factor;
+        self.normal = self.normal * factor;
+        self.emission = self.emission * factor;
+        self.direct = self.direct * factor;
+        self.indirect = self.indirect * factor;
+        self.occlusion *= factor;
+        for (_, radiance) in self.lights.iter_mut() {
+            *radiance = *radiance * factor;
+        }
+    }
+}
+
+impl Checkpointed for LightId {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Background => None,
+            Self::Object(id) => Some(*id),
+        }
+        .save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Option::load(input)?.map_or(Self::Background, Self::Object))
+    }
+}
+
+impl Checkpointed for Aovs {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.albedo.save(out);
+        self.normal.save(out);
+        self.depth.save(out);
+        self.emission.save(out);
+        self.direct.save(out);
+        self.indirect.save(out);
+        self.lights.save(out);
+        self.occlusion.save(out);
+    }
+
+    fn load(input: &mut &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            albedo: Rgb::load(input)?,
+            normal: Vec3::load(input)?,
+            depth: f64::load(input)?,
+            emission: Rgb::load(input)?,
+            direct: Rgb::load(input)?,
+            indirect: Rgb::load(input)?,
+            lights: Vec::load(input)?,
+            occlusion: f64::load(input)?,
+        })
+    }
+}
+
+/// Contributions a camera sample makes to pixels other than its own, such as
+/// light paths connected straight to the lens.
+pub struct Splats<'a> {
+    pub viewport: &'a Viewport,
+    /// Pixel index and radiance, in the same units as the sample's radiance.
+    pub samples: Vec<(usize, Rgb)>,
+}
+
+/// A light transport algorithm: estimates the radiance arriving along a camera ray.
+pub trait Integrator: Send + Sync {
+    /// Returns RGB radiance, or one value per path wavelength for spectral rays.
+    fn radiance(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+    ) -> Rgb;
+
+    /// Like `radiance`, also filling `aovs`. Integrators that don't follow light
+    /// paths only record the first-hit surface data.
+    fn radiance_with_aovs(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+        aovs: &mut Aovs,
+    ) -> Rgb {
+        aovs.record_surface(&ray, world.hit(&ray, 0.00001..f64::INFINITY).as_ref());
+        self.radiance(ray, world, background, rng)
+    }
+
+    /// What the camera calls: `radiance_with_aovs` or `radiance`, for
+    /// integrators that only add to the sampled pixel. Others also push to
+    /// `splats`.
+    fn radiance_with_splats(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+        aovs: Option<&mut Aovs>,
+        _splats: &mut Splats,
+    ) -> Rgb {
+        match aovs {
+            Some(aovs) => self.radiance_with_aovs(ray, world, background, rng, aovs),
+            None => self.radiance(ray, world, background, rng),
+        }
+    }
+
+    /// For integrators whose samples aren't independent per pixel: renders
+    /// the whole image, returning each pixel's film value (see `to_film`),
+    /// and reports the film so far to `progress` now and then. The camera
+    /// then skips its own per-pixel sampling and AOVs.
+    fn render_film(
+        &self,
+        _camera: &Camera,
+        _world: &dyn Hittable,
+        _progress: &mut FilmProgress,
+    ) -> Option<Vec<Rgb>> {
+        None
+    }
+}
+
+/// Unidirectional path tracer. After `roulette_depth` bounces, paths are
+/// randomly terminated with a probability based on their throughput, and
+/// survivors are reweighted so the estimate stays unbiased.
+pub struct PathTracer {
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+}
+
+impl PathTracer {
+    pub fn new(max_depth: i32) -> Self {
+        Self {
+            max_depth,
+            roulette_depth: 3,
+        }
+    }
+}
+
+impl PathTracer {
+    fn trace(
+        &self,
+        mut ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        mut rng: &mut dyn RngCore,
+        mut aovs: Option<&mut Aovs>,
+    ) -> Rgb {
+        let mut radiance = Rgb::BLACK;
+        let mut throughput = Rgb::new(1.0, 1.0, 1.0);
+
+        for depth in 0..self.max_depth {
+            let hit = world.hit(&ray, 0.00001..f64::INFINITY);
+
+            if depth == 0
+                && let Some(aovs) = aovs.as_deref_mut()
+            {
+                aovs.record_surface(&ray, hit.as_ref());
+            }
+
+            let Some(h) = hit else {
+                let contribution = throughput * background.color(&ray);
+                if let Some(aovs) = aovs {
+                    aovs.record_light(depth, LightId::Background, contribution);
+                }
+                return radiance + contribution;
+            };
+
+            let emitted = h.mat.emitted(&ray);
+            if emitted != Rgb::BLACK {
+                let contribution = throughput * emitted;
+                if let Some(aovs) = aovs.as_deref_mut() {
+                    aovs.record_light(depth, LightId::Object(h.object_id), contribution);
+                }
+                radiance = radiance + contribution;
+            }
+
+            let Some((attenuation, new_ray)) = h.mat.scatter(&ray, &h, &mut rng) else {
+                return radiance;
+            };
+
+            throughput = throughput * attenuation;
+            ray = new_ray;
+
+            if depth >= self.roulette_depth {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+                if rng.random::<f64>() >= survival {
+                    return radiance;
+                }
+                throughput = throughput / survival;
+            }
+        }
+
+        radiance
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+    ) -> Rgb {
+        self.trace(ray, world, background, rng, None)
+    }
+
+    fn radiance_with_aovs(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+        aovs: &mut Aovs,
+    ) -> Rgb {
+        self.trace(ray, world, background, rng, Some(aovs))
+    }
+}
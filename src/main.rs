@@ -1,31 +1,347 @@
+mod aabb;
+mod adaptive;
+mod ambient_occlusion;
+mod bdpt;
 mod camera;
+mod cancel;
+mod checkpoint;
+mod cie;
+mod color_space;
+mod crop;
+mod debug_integrator;
+mod denoise;
+mod distributed;
+mod filter;
 mod hittable;
 mod image;
+mod image_reader;
 mod image_writer;
+mod integrator;
 mod material;
+mod mlt;
 mod point;
+mod preview_server;
 mod ray;
 mod rbg;
+mod rgb_to_spectrum;
+mod sampler;
+mod spectrum;
+mod sppm;
+mod tile;
 mod vec3;
+mod volume;
 
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use rand::{Rng, rng};
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use rand::{Rng, SeedableRng, random, rngs::SmallRng};
 
 use crate::{
-    camera::Camera,
+    aabb::Aabb,
+    adaptive::AdaptiveSampling,
+    ambient_occlusion::AmbientOcclusion,
+    bdpt::Bdpt,
+    camera::{Camera, JobSplit, PassStats, RenderObserver},
+    checkpoint::{CheckpointFile, Checkpointing},
+    color_space::ColorSpace,
+    crop::CropWindow,
+    debug_integrator::{DebugChannel, DebugIntegrator, heatmap},
+    denoise::Denoiser,
+    filter::{Filter, FilterKind},
     hittable::{Hittables, Sphere},
-    image_writer::{ImageWriter, PpmFileWriter},
-    material::Material,
+    image::Image,
+    image_reader::read_image,
+    image_writer::{ExrFileWriter, ImageWriter, PngFileWriter, PpmFileWriter},
+    integrator::{Background, PathTracer},
+    material::{Dispersion, Material},
+    mlt::Mlt,
     point::Point3,
+    preview_server::PreviewServer,
     rbg::Rgb,
+    sampler::SamplerKind,
+    spectrum::{Emission, Fluorescent, Spd},
+    sppm::Sppm,
+    tile::{Tile, TileOrder},
     vec3::Vec3,
+    volume::{GridVolume, VolumeGrids, VoxelGrid, load_volume_file},
 };
 
-fn random_world() -> Hittables {
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Scene {
+    /// The final scene from "Ray Tracing in One Weekend"
+    Random,
+    /// A voxel grid volume over a ground plane
+    Volume,
+    /// Clear, coloured and dispersive glass spheres
+    Glass,
+    /// Grey and coloured spheres lit by a row of standard illuminants
+    Lights,
+    /// A glass sphere focusing a small light onto the floor
+    Caustic,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum IntegratorKind {
+    /// Unidirectional path tracing
+    Path,
+    /// Bidirectional path tracing, for caustics and hard to reach lights
+    Bdpt,
+    /// Stochastic progressive photon mapping, one iteration per `--spp`
+    Sppm,
+    /// Primary sample space Metropolis light transport, `--spp` mutations per
+    /// pixel on average
+    Mlt,
+    /// Geometry and material inspection, see `--debug-channel`
+    Debug,
+    /// Ambient occlusion, see `--ao-samples` and `--ao-distance`
+    Ao,
+}
+
+#[derive(Clone, Debug, Parser)]
+struct Args {
+    /// Path of the image to write; `.exr` files get linear floats and AOV
+    /// layers, `.png` files 8-bit color, anything else is written as PPM
+    #[arg(short, long, default_value = "out.ppm")]
+    output: PathBuf,
+
+    /// Keep rewriting this image with the tiles rendered so far, at most
+    /// once a second
+    #[arg(long)]
+    preview: Option<PathBuf>,
+
+    /// Serve a page to watch the render converge on at this address, e.g.
+    /// `127.0.0.1:8000`
+    #[arg(long)]
+    serve: Option<String>,
+
+    /// Keep serving the final image for this many seconds once the render is
+    /// done, or until Ctrl-C
+    #[arg(long, requires = "serve", value_parser = positive)]
+    serve_linger: Option<f64>,
+
+    /// Render in passes of this many samples per pixel, overwriting the
+    /// output with the image so far after each
+    #[arg(long)]
+    progressive: Option<u32>,
+
+    /// With `--progressive`, only write the image after a pass if this many
+    /// seconds have gone by since the last time [default: every pass]
+    #[arg(long, requires = "progressive")]
+    snapshot_interval: Option<f64>,
+
+    /// Save the render's progress here every `--checkpoint-interval`
+    /// seconds and when it's cancelled, and remove it once the finished
+    /// image is written
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 60.0, value_parser = positive)]
+    checkpoint_interval: f64,
+
+    /// Continue the render saved at `--checkpoint`, with its seed. Other
+    /// settings must match the ones it was started with.
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+
+    /// Hand the render out to workers connecting to this address, e.g.
+    /// `0.0.0.0:7878`, instead of rendering it here. Every sample is taken in
+    /// one go.
+    #[arg(long, conflicts_with_all = ["progressive", "time_budget", "target_noise", "adaptive_threshold", "checkpoint"])]
+    coordinator: Option<String>,
+
+    /// How the coordinator splits the render into jobs
+    #[arg(long, value_enum, default_value_t = JobSplit::Tiles)]
+    distribute: JobSplit,
+
+    /// Samples per job with `--distribute samples`
+    #[arg(long, default_value_t = 16)]
+    job_samples: u32,
+
+    /// Render jobs for the coordinator at this address until it's done. The
+    /// scene and settings are the coordinator's, and files it reads, like
+    /// `--volume`, must be at the same path here.
+    #[arg(long, conflicts_with = "coordinator")]
+    worker: Option<String>,
+
+    /// Only render the `WIDTH` by `HEIGHT` pixels from `X,Y` on
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "X,Y,WIDTH,HEIGHT",
+        group = "crop_area"
+    )]
+    crop: Option<Vec<usize>>,
+
+    /// Only render the part of the image from corner `X0,Y0` to `X1,Y1`, as
+    /// fractions of its width and height
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "X0,Y0,X1,Y1",
+        group = "crop_area"
+    )]
+    crop_window: Option<Vec<f64>>,
+
+    /// With a crop, write the full-size image with only the crop rendered
+    #[arg(long, requires = "crop_area")]
+    full_frame: bool,
+
+    /// With a crop, paste it into this earlier render of the whole image,
+    /// and write that
+    #[arg(long, requires = "crop_area", conflicts_with = "full_frame")]
+    compose: Option<PathBuf>,
+
+    /// Width and height of the square tiles the image is rendered in
+    #[arg(long, default_value_t = 16)]
+    tile_size: usize,
+
+    /// Order in which tiles are rendered
+    #[arg(long, value_enum, default_value_t = TileOrder::Spiral)]
+    tile_order: TileOrder,
+
+    /// Also render albedo, normal, depth, emission, direct/indirect, ambient
+    /// occlusion, per-light and variance layers (written to EXR output)
+    #[arg(long)]
+    aovs: bool,
+
+    /// Denoise the image, guided by the albedo, normal and variance layers
+    /// (recorded even without `--aovs`)
+    #[arg(long)]
+    denoise: bool,
+
+    /// Blur passes of the denoiser, each reaching twice as far
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..=10))]
+    denoise_passes: u32,
+
+    #[arg(long, value_enum, default_value_t = Scene::Random)]
+    scene: Scene,
+
+    #[arg(long, default_value_t = 1200)]
+    width: usize,
+
+    /// Samples per pixel; the most a pixel takes with adaptive sampling, a
+    /// time budget or a target noise [default: 500, unlimited with
+    /// `--time-budget` or `--target-noise`]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    spp: Option<u32>,
+
+    /// Keep rendering passes for at most this many seconds
+    #[arg(long, value_parser = positive)]
+    time_budget: Option<f64>,
+
+    /// Keep rendering passes until the mean relative standard error of the
+    /// pixels' luminance is this low
+    #[arg(long, value_parser = positive)]
+    target_noise: Option<f64>,
+
+    /// Stop sampling a pixel once the standard error of its luminance falls
+    /// below this fraction of the luminance
+    #[arg(long, value_parser = positive)]
+    adaptive_threshold: Option<f64>,
+
+    /// Samples every pixel takes before adaptive sampling may stop it
+    #[arg(long, default_value_t = 16)]
+    min_spp: u32,
+
+    /// Also write a heatmap of the samples each pixel took, from blue for
+    /// `--min-spp` to red for the most any pixel took
+    #[arg(long, requires = "adaptive_threshold")]
+    sample_heatmap: Option<PathBuf>,
+
+    /// Seed for the scene and every sample, for bit-identical renders
+    /// [default: random]
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How the samples of a pixel are spread over the random number dimensions
+    #[arg(long, value_enum, default_value_t = SamplerKind::Sobol)]
+    sampler: SamplerKind,
+
+    /// Pixel reconstruction filter
+    #[arg(long, value_enum, default_value_t = FilterKind::Box)]
+    filter: FilterKind,
+
+    /// Filter radius in pixels [default: depends on the filter]
+    #[arg(long, value_parser = positive)]
+    filter_radius: Option<f64>,
+
+    #[arg(long, default_value_t = 50)]
+    max_depth: i32,
+
+    #[arg(long, value_enum, default_value_t = IntegratorKind::Path)]
+    integrator: IntegratorKind,
+
+    /// What the debug integrator shows
+    #[arg(long, value_enum, default_value_t = DebugChannel::Normal)]
+    debug_channel: DebugChannel,
+
+    /// Occlusion rays per camera sample for the AO integrator
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    ao_samples: u32,
+
+    /// How far occlusion rays look, for the AO integrator and the AO layer
+    #[arg(long, default_value_t = 2.0)]
+    ao_distance: f64,
+
+    /// Initial photon gather radius for SPPM, in scene units
+    #[arg(long, default_value_t = 0.1, value_parser = positive)]
+    photon_radius: f64,
+
+    /// Photons per SPPM iteration [default: one per pixel]
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    photons: Option<usize>,
+
+    /// Markov chains run by the MLT integrator
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    mlt_chains: usize,
+
+    /// Independent paths MLT traces to normalise the image and seed its chains
+    #[arg(
+        long,
+        default_value_t = 100_000,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    mlt_bootstrap: usize,
+
+    /// Probability of an MLT mutation drawing a completely new path
+    #[arg(long, default_value_t = 0.3, value_parser = probability)]
+    mlt_large_step: f64,
+
+    /// Bounces before Russian roulette starts terminating dim paths
+    #[arg(long, default_value_t = 3)]
+    roulette_depth: i32,
+
+    /// Grids for the volume scene: a sparse text volume, or headerless f32 data
+    /// when `--volume-dims` is given. A procedural cloud is used when omitted.
+    #[arg(long)]
+    volume: Option<PathBuf>,
+
+    /// Trace spectral paths, so dispersive glass splits light and lights use
+    /// their full spectral power distributions
+    #[arg(long)]
+    spectral: bool,
+
+    /// Colour space of the written image
+    #[arg(long, value_enum, default_value_t = ColorSpace::Srgb)]
+    color_space: ColorSpace,
+
+    /// Dimensions of a raw `--volume` grid, e.g. `64,64,64`
+    #[arg(long, value_delimiter = ',', num_args = 3)]
+    volume_dims: Option<Vec<usize>>,
+}
+
+fn random_world(seed: u64) -> Hittables {
     let mut world = Hittables::new();
 
-    let mut rng = rng();
+    let mut rng = SmallRng::seed_from_u64(seed);
 
     //ground
     world.add(Sphere::new(
@@ -70,6 +386,8 @@ fn random_world() -> Hittables {
                         0.2,
                         Material::Dielectric {
                             refraction_index: 1.5,
+                            absorption: Rgb::BLACK,
+                            dispersion: Dispersion::None,
                         },
                     ));
                 }
@@ -82,6 +400,8 @@ fn random_world() -> Hittables {
         1.0,
         Material::Dielectric {
             refraction_index: 1.5,
+            absorption: Rgb::BLACK,
+            dispersion: Dispersion::None,
         },
     ));
 
@@ -105,24 +425,662 @@ fn random_world() -> Hittables {
     world
 }
 
+fn glass_world() -> anyhow::Result<Hittables> {
+    let mut world = Hittables::new();
+
+    world.add(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Rgb::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric {
+            refraction_index: 1.5,
+            absorption: Rgb::BLACK,
+            dispersion: Dispersion::BK7,
+        },
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(2.0, 0.5, 2.5),
+        0.5,
+        Material::Dielectric {
+            refraction_index: 1.77,
+            absorption: Rgb::BLACK,
+            dispersion: Dispersion::DENSE_FLINT,
+        },
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(-1.5, 0.4, 2.5),
+        0.4,
+        Material::Dielectric {
+            refraction_index: 2.42,
+            absorption: Rgb::BLACK,
+            dispersion: Dispersion::DIAMOND,
+        },
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::tinted_dielectric(1.5, Rgb::new(0.3, 0.8, 0.4), 1.0)?,
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(4.0, 1.0, 0.0),
+        1.0,
+        Material::tinted_dielectric(1.33, Rgb::new(0.6, 0.8, 0.95), 2.0)?,
+    ));
+
+    Ok(world)
+}
+
+fn lights_world() -> Hittables {
+    let mut world = Hittables::new();
+
+    world.add(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Rgb::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    let subjects = [
+        Rgb::new(0.8, 0.8, 0.8),
+        Rgb::new(0.8, 0.1, 0.1),
+        Rgb::new(0.1, 0.7, 0.2),
+        Rgb::new(0.1, 0.2, 0.8),
+    ];
+    for (i, albedo) in subjects.into_iter().enumerate() {
+        world.add(Sphere::new(
+            Vec3::new(0.0, 0.7, 3.0 - 2.0 * i as f64),
+            0.7,
+            Material::Lambertian { albedo },
+        ));
+    }
+
+    let illuminants = [
+        Spd::Blackbody {
+            temperature: 1900.0,
+        },
+        Spd::IlluminantA,
+        Spd::D65,
+        Spd::Fluorescent(Fluorescent::F2),
+        Spd::Fluorescent(Fluorescent::F7),
+        Spd::Fluorescent(Fluorescent::F11),
+    ];
+    for (i, spd) in illuminants.into_iter().enumerate() {
+        world.add(Sphere::new(
+            Vec3::new(2.0, 3.0, 5.0 - 2.0 * i as f64),
+            0.5,
+            Material::DiffuseLight {
+                emission: Emission::new(spd, 8.0),
+            },
+        ));
+    }
+
+    world
+}
+
+fn caustic_world() -> Hittables {
+    let mut world = Hittables::new();
+
+    world.add(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Rgb::new(0.7, 0.7, 0.7),
+        },
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        1.0,
+        Material::Dielectric {
+            refraction_index: 1.5,
+            absorption: Rgb::BLACK,
+            dispersion: Dispersion::BK7,
+        },
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(-1.0, 1.5, -1.0),
+        0.5,
+        Material::Lambertian {
+            albedo: Rgb::new(0.8, 0.3, 0.2),
+        },
+    ));
+
+    world.add(Sphere::new(
+        Vec3::new(0.5, 5.0, 1.5),
+        0.2,
+        Material::DiffuseLight {
+            emission: Emission::new(Spd::D65, 200.0),
+        },
+    ));
+
+    world
+}
+
+fn procedural_cloud() -> VolumeGrids {
+    let dims = [64, 64, 64];
+    let offset = |uvw: Vec3| uvw - Vec3::new(0.5, 0.5, 0.5);
+
+    let density = VoxelGrid::from_fn(dims, |uvw| {
+        let p = offset(uvw);
+        let wobble = 0.08 * (f64::sin(17.0 * p.x) * f64::sin(13.0 * p.y) * f64::sin(19.0 * p.z));
+        (1.0 - (p.len() + wobble) / 0.5).clamp(0.0, 1.0)
+    });
+
+    let temperature = VoxelGrid::from_fn(dims, |uvw| {
+        let core = (1.0 - offset(uvw).len() / 0.25).clamp(0.0, 1.0);
+        1500.0 * core
+    });
+
+    VolumeGrids {
+        density,
+        emission: None,
+        temperature: Some(temperature),
+    }
+}
+
+fn volume_world(args: &Args) -> anyhow::Result<Hittables> {
+    let mut world = Hittables::new();
+
+    world.add(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian {
+            albedo: Rgb::new(0.5, 0.5, 0.5),
+        },
+    ));
+
+    let grids = match (&args.volume, &args.volume_dims) {
+        (Some(path), Some(dims)) => VolumeGrids {
+            density: VoxelGrid::load_raw(path, [dims[0], dims[1], dims[2]])?,
+            emission: None,
+            temperature: None,
+        },
+        (Some(path), None) => load_volume_file(path)?,
+        (None, _) => procedural_cloud(),
+    };
+
+    let mut volume = GridVolume::new(
+        Aabb::new(Point3::new(-2.0, 0.0, -2.0), Point3::new(2.0, 4.0, 2.0)),
+        grids,
+    );
+    volume.density_scale = 4.0;
+    volume.temperature_scale = 2.0;
+    world.add(volume);
+
+    Ok(world)
+}
+
 fn main() -> anyhow::Result<()> {
-    let aspect_ratio = 16.0 / 9.0 as f64;
-    let image_width: usize = 1200;
+    let args = Args::parse();
+    if let Some(address) = &args.worker {
+        return distributed::work(address, |coordinator_args, seed| {
+            let mut args = Args::try_parse_from(coordinator_args)?;
+            args.seed = Some(seed);
+            setup(&args)
+        });
+    }
+
+    let (camera, world) = setup(&args)?;
+
+    // The first Ctrl-C stops the render and writes the image so far
+    let cancel = camera.cancel.clone();
+    ctrlc::set_handler(move || {
+        if cancel.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("\nStopping, press Ctrl-C again to quit without writing the image");
+        cancel.cancel();
+    })
+    .context("Unable to handle Ctrl-C")?;
+
+    let mut outputs = RenderOutputs::new(&args, &camera)?;
+    let mut img = match &args.coordinator {
+        Some(address) => {
+            let jobs = camera.jobs(args.distribute, args.job_samples)?;
+            distributed::coordinate(
+                &camera,
+                address,
+                std::env::args().collect(),
+                &jobs,
+                &mut outputs,
+            )?
+        }
+        None => camera.render(&world, &mut outputs)?,
+    };
+    std::mem::replace(&mut outputs.error, Ok(()))?;
+    let cancelled = camera.cancel.is_cancelled();
+    if let Some(stats) = &outputs.last_pass {
+        // Integrators rendering the whole film don't know their noise
+        let noise = if stats.noise.is_finite() {
+            format!(", noise {:.4}", stats.noise)
+        } else {
+            String::new()
+        };
+        eprintln!(
+            "{} {:.1} spp in {:.1?}{noise}",
+            if cancelled {
+                "Cancelled after"
+            } else {
+                "Rendered"
+            },
+            stats.mean_samples,
+            stats.elapsed,
+        );
+    }
+    if let Some(path) = &args.preview {
+        image_writer(path)?.write(&img)?;
+    }
+    outputs.write_output(&mut img)?;
+    if let Some(path) = &args.checkpoint
+        && path.exists()
+    {
+        if cancelled {
+            eprintln!("Continue the render with --resume");
+        } else {
+            // The image is safe, so the render won't need resuming
+            std::fs::remove_file(path).with_context(|| {
+                format!("Unable to remove the checkpoint at {}", path.display())
+            })?;
+        }
+    }
+
+    if let Some(path) = &args.sample_heatmap {
+        let samples = img
+            .layer("samples")
+            .context("adaptive render without a sample count layer")?;
+
+        let mut map = Image::new(img.width, img.height);
+        let most = samples.iter().map(|n| n.r()).fold(0.0, f64::max);
+        let range = (most - args.min_spp as f64).max(1.0);
+        for (pixel, n) in map.as_mut_slice().iter_mut().zip(samples.iter()) {
+            *pixel = heatmap((n.r() - args.min_spp as f64) / range);
+        }
+        image_writer(path)?.write(&map)?;
+    }
+
+    if let Some(server) = &outputs.server {
+        server.finish(&img, outputs.last_pass.as_ref())?;
+        if let Some(linger) = args.serve_linger {
+            eprintln!("Serving the final image for {linger}s, press Ctrl-C to quit");
+            // The image is written, so Ctrl-C can quit straight away
+            camera.cancel.cancel();
+            std::thread::sleep(Duration::from_secs_f64(linger));
+        }
+    }
+
+    // Like quitting outright, so scripts can tell the image is partial
+    if cancelled {
+        std::process::exit(130);
+    }
+
+    Ok(())
+}
+
+/// The camera and scene `args` describe.
+fn setup(args: &Args) -> anyhow::Result<(Camera, Hittables)> {
+    let aspect_ratio = 16.0 / 9.0;
+    anyhow::ensure!(
+        (args.width as f64 / aspect_ratio) as usize >= 1,
+        "`--width` {} is too small for an image at least 1 pixel high",
+        args.width
+    );
 
-    let mut camera = Camera::new(aspect_ratio, image_width, 500, 50);
+    let open_ended = args.time_budget.is_some() || args.target_noise.is_some();
+    let whole_film = matches!(args.integrator, IntegratorKind::Sppm | IntegratorKind::Mlt);
+    anyhow::ensure!(
+        !open_ended || !whole_film,
+        "`--time-budget` and `--target-noise` need an integrator rendering in passes"
+    );
+    anyhow::ensure!(
+        args.checkpoint.is_none() || !whole_film,
+        "`--checkpoint` needs an integrator rendering in passes"
+    );
+    anyhow::ensure!(
+        args.coordinator.is_none() || !whole_film,
+        "`--coordinator` needs an integrator rendering in passes"
+    );
+    anyhow::ensure!(
+        !(args.aovs || args.denoise) || !whole_film,
+        "`--aovs` and `--denoise` need an integrator rendering in passes"
+    );
+    anyhow::ensure!(
+        args.adaptive_threshold.is_none() || !whole_film,
+        "`--adaptive-threshold` needs an integrator rendering in passes"
+    );
+    let spp = samples_per_pixel(args);
+
+    let mut camera = Camera::new(aspect_ratio, args.width, spp, args.max_depth);
+    camera.integrator = match args.integrator {
+        IntegratorKind::Path => Box::new(PathTracer {
+            max_depth: args.max_depth,
+            roulette_depth: args.roulette_depth,
+        }),
+        IntegratorKind::Bdpt => Box::new(Bdpt::new(args.max_depth)),
+        IntegratorKind::Sppm => {
+            let mut sppm = Sppm::new(args.max_depth, args.photon_radius);
+            sppm.photons_per_iteration = args.photons;
+            Box::new(sppm)
+        }
+        IntegratorKind::Mlt => {
+            let mut mlt = Mlt::new(PathTracer {
+                max_depth: args.max_depth,
+                roulette_depth: args.roulette_depth,
+            });
+            mlt.chains = args.mlt_chains;
+            mlt.bootstrap_samples = args.mlt_bootstrap;
+            mlt.large_step_probability = args.mlt_large_step;
+            Box::new(mlt)
+        }
+        IntegratorKind::Debug => Box::new(DebugIntegrator::new(args.debug_channel, args.max_depth)),
+        IntegratorKind::Ao => Box::new(AmbientOcclusion::new(args.ao_samples, args.ao_distance)),
+    };
     camera.vfov = 20.0;
     camera.look_from = Point3::new(13.0, 2.0, 3.0);
     camera.look_at = Point3::new(0.0, 0.0, -1.0);
     camera.vup = Vec3::new(0.0, 1.0, 0.0);
     camera.defocus_angle = 0.6;
     camera.focus_dist = 10.0;
+    camera.adaptive = args
+        .adaptive_threshold
+        .map(|threshold| AdaptiveSampling::new(args.min_spp, threshold));
+    camera.sampler = args.sampler;
+    camera.pass_samples = args.progressive;
+    camera.time_budget = args.time_budget.map(Duration::from_secs_f64);
+    camera.target_noise = args.target_noise;
+    anyhow::ensure!(
+        [
+            args.crop.as_ref().map(Vec::len),
+            args.crop_window.as_ref().map(Vec::len)
+        ]
+        .iter()
+        .all(|len| len.is_none_or(|len| len == 4)),
+        "crops take 4 comma separated values"
+    );
+    camera.crop = match (&args.crop, &args.crop_window) {
+        (Some(pixels), _) => Some(CropWindow::Pixels(Tile {
+            x: pixels[0],
+            y: pixels[1],
+            width: pixels[2],
+            height: pixels[3],
+        })),
+        (None, Some(corners)) => Some(CropWindow::Normalized {
+            min: (corners[0], corners[1]),
+            max: (corners[2], corners[3]),
+        }),
+        (None, None) => None,
+    };
+    camera.crop_full_frame = args.full_frame;
+    camera.tile_size = args.tile_size;
+    camera.tile_order = args.tile_order;
+    camera.filter = Filter::new(args.filter);
+    if let Some(radius) = args.filter_radius {
+        camera.filter.radius = radius;
+    }
+    camera.seed = match &args.checkpoint {
+        // The scene is built from the seed too, so it has to be known up front
+        Some(path) if args.resume => {
+            let checkpoint = CheckpointFile::read(path)?;
+            let started = Args::try_parse_from(&checkpoint.args)
+                .context("the checkpoint's command line no longer parses")?;
+            let (started_spp, spp) = (samples_per_pixel(&started), samples_per_pixel(args));
+            let describe = |spp| match spp {
+                u32::MAX => "unlimited samples per pixel".to_string(),
+                spp => format!("{spp} samples per pixel"),
+            };
+            anyhow::ensure!(
+                started_spp == spp,
+                "the checkpoint at {} was made with {}, not {}, resume it with\n  {}",
+                path.display(),
+                describe(started_spp),
+                describe(spp),
+                checkpoint.args.join(" ")
+            );
+            anyhow::ensure!(
+                sample_settings(&started) == sample_settings(args),
+                "the checkpoint at {} was made with different settings, resume it with\n  {}",
+                path.display(),
+                checkpoint.args.join(" ")
+            );
+            checkpoint.seed
+        }
+        _ => args.seed.unwrap_or_else(random),
+    };
+    camera.checkpoint = args.checkpoint.clone().map(|path| {
+        Checkpointing::new(
+            path,
+            Duration::from_secs_f64(args.checkpoint_interval),
+            args.resume,
+            std::env::args().collect(),
+        )
+    });
+    camera.spectral = args.spectral;
+    camera.color_space = args.color_space;
+    camera.aovs = args.aovs || args.denoise;
+    camera.occlusion.max_distance = args.ao_distance;
 
-    let world = random_world();
+    let world = match args.scene {
+        Scene::Random => random_world(camera.seed),
+        Scene::Volume => volume_world(args)?,
+        Scene::Glass => glass_world()?,
+        Scene::Lights => {
+            camera.background = Background::Solid(Rgb::BLACK);
+            lights_world()
+        }
+        Scene::Caustic => {
+            camera.background = Background::Solid(Rgb::BLACK);
+            caustic_world()
+        }
+    };
 
-    let img = camera.render(&world);
+    Ok((camera, world))
+}
 
-    let mut writer = PpmFileWriter::new(Path::new("out.ppm"))?;
-    writer.write(&img)?;
+/// Parses a number greater than 0.
+fn positive(arg: &str) -> Result<f64, String> {
+    let value: f64 = arg.parse().map_err(|err| format!("{err}"))?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err("must be a number greater than 0".to_string())
+    }
+}
 
-    Ok(())
+/// Parses a number from 0 to 1.
+fn probability(arg: &str) -> Result<f64, String> {
+    let value: f64 = arg.parse().map_err(|err| format!("{err}"))?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err("must be a number from 0 to 1".to_string())
+    }
+}
+
+/// `--spp`, or its default: unlimited when the render stops on time or noise.
+fn samples_per_pixel(args: &Args) -> u32 {
+    let open_ended = args.time_budget.is_some() || args.target_noise.is_some();
+    args.spp.unwrap_or(if open_ended { u32::MAX } else { 500 })
+}
+
+/// Settings of `args` that change a render's samples, as text, so a resumed
+/// render can be checked against the one it continues. Where the image goes,
+/// when the render stops and how it's denoised can change between runs; the
+/// seed comes from the checkpoint. `--spp` is compared as resolved, since its
+/// default depends on when the render stops.
+fn sample_settings(args: &Args) -> String {
+    let mut args = args.clone();
+    args.spp = Some(samples_per_pixel(&args));
+    args.output = PathBuf::new();
+    args.preview = None;
+    args.serve = None;
+    args.serve_linger = None;
+    args.snapshot_interval = None;
+    args.checkpoint = None;
+    args.checkpoint_interval = 0.0;
+    args.resume = false;
+    args.seed = None;
+    args.time_budget = None;
+    args.target_noise = None;
+    args.sample_heatmap = None;
+    args.denoise_passes = 0;
+    args.full_frame = false;
+    args.compose = None;
+    format!("{args:?}")
+}
+
+/// Writes the tile preview and progressive snapshots of a render in
+/// progress. The first error stops further writes.
+struct RenderOutputs<'a> {
+    args: &'a Args,
+    preview: Image,
+    last_preview: Instant,
+    server: Option<PreviewServer>,
+    last_publish: Instant,
+    last_snapshot: Instant,
+    last_pass: Option<PassStats>,
+    /// Earlier render the crop is pasted into, and where it goes.
+    compose: Option<(Image, Tile)>,
+    error: anyhow::Result<()>,
+}
+
+impl<'a> RenderOutputs<'a> {
+    fn new(args: &'a Args, camera: &Camera) -> anyhow::Result<Self> {
+        let view = camera.viewport();
+        let compose = match &args.compose {
+            Some(path) => {
+                let base = read_image(path)?;
+                anyhow::ensure!(
+                    (base.width, base.height) == (view.width(), view.height()),
+                    "{} is {}x{}, not {}x{} like the render",
+                    path.display(),
+                    base.width,
+                    base.height,
+                    view.width(),
+                    view.height()
+                );
+                Some((base, camera.crop_pixels()?))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            args,
+            preview: Image::new(view.width(), view.height()),
+            last_preview: Instant::now(),
+            server: match &args.serve {
+                Some(address) => Some(PreviewServer::start(address, view.width(), view.height())?),
+                None => None,
+            },
+            last_publish: Instant::now(),
+            last_snapshot: Instant::now(),
+            last_pass: None,
+            compose,
+            error: Ok(()),
+        })
+    }
+
+    /// Denoises `img` if asked to and writes it to the output path, pasted
+    /// into the image it's composed with if there is one.
+    fn write_output(&mut self, img: &mut Image) -> anyhow::Result<()> {
+        if self.args.denoise {
+            Denoiser::new(self.args.denoise_passes)
+                .denoise(img)
+                .context("the integrator doesn't record AOVs")?;
+        }
+
+        let mut writer = image_writer(&self.args.output)?;
+        match &mut self.compose {
+            Some((base, window)) => {
+                base.paste(img, window.x, window.y);
+                writer.write(base)
+            }
+            None => writer.write(img),
+        }
+    }
+}
+
+impl RenderObserver for RenderOutputs<'_> {
+    fn tile_done(&mut self, tile: &Tile, values: &[Rgb]) {
+        if self.args.preview.is_none() && self.server.is_none() {
+            return;
+        }
+        for (idx, value) in tile.pixels(self.preview.width).zip(values) {
+            self.preview.as_mut_slice()[idx] = *value;
+        }
+
+        if let Some(path) = &self.args.preview
+            && self.error.is_ok()
+            && self.last_preview.elapsed() >= PREVIEW_INTERVAL
+        {
+            self.error = image_writer(path)
+                .and_then(|mut writer| writer.write(&self.preview))
+                .context("failed to write the preview");
+            self.last_preview = Instant::now();
+        }
+        if let Some(server) = &self.server
+            && self.error.is_ok()
+            && self.last_publish.elapsed() >= PUBLISH_INTERVAL
+        {
+            self.error = server
+                .publish(&self.preview, self.last_pass.as_ref())
+                .context("failed to serve the preview");
+            self.last_publish = Instant::now();
+        }
+    }
+
+    fn pass_done(&mut self, stats: &PassStats, snapshot: &dyn Fn() -> Image) {
+        self.last_pass = Some(*stats);
+        if let Some(server) = &self.server
+            && self.error.is_ok()
+            && self.last_publish.elapsed() >= PUBLISH_INTERVAL
+        {
+            self.error = server
+                .publish(&self.preview, Some(stats))
+                .context("failed to serve the preview");
+            self.last_publish = Instant::now();
+        }
+        if self.args.progressive.is_none() || self.error.is_err() {
+            return;
+        }
+        if let Some(interval) = self.args.snapshot_interval
+            && self.last_snapshot.elapsed().as_secs_f64() < interval
+        {
+            return;
+        }
+
+        self.error = self
+            .write_output(&mut snapshot())
+            .with_context(|| format!("failed to write the snapshot at {} spp", stats.samples));
+        self.last_snapshot = Instant::now();
+    }
+
+    fn cancelled(&mut self, stats: &PassStats) {
+        self.last_pass = Some(*stats);
+    }
+}
+
+const PREVIEW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often tiles and passes update the served preview.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// EXR writer for `.exr` paths, PNG for `.png`, PPM otherwise.
+fn image_writer(path: &Path) -> anyhow::Result<Box<dyn ImageWriter>> {
+    Ok(match path.extension().and_then(|ext| ext.to_str()) {
+        Some("exr") => Box::new(ExrFileWriter::new(path)?),
+        Some("png") => Box::new(PngFileWriter::new(path)?),
+        _ => Box::new(PpmFileWriter::new(path)?),
+    })
 }
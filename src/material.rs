@@ -1,34 +1,135 @@
+use anyhow::ensure;
 use rand::Rng;
 
 use crate::{
     hittable::HitRecord,
     ray::Ray,
     rbg::Rgb,
+    rgb_to_spectrum::{illuminant, reflectance},
+    spectrum::{Emission, SampledWavelengths},
     vec3::{Vec3, dot, norm, rand_unit_vec},
 };
 
+/// Wavelength dependence of a dielectric's index of refraction.
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    None,
+    /// n(λ) = a + b / λ², with λ in micrometres.
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// n²(λ) = 1 + Σ bᵢ λ² / (λ² - cᵢ), with λ in micrometres.
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Dispersion {
+    pub const BK7: Self = Self::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+
+    pub const DENSE_FLINT: Self = Self::Cauchy {
+        a: 1.7280,
+        b: 0.01342,
+    };
+
+    pub const DIAMOND: Self = Self::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+
+    /// Index of refraction at `wavelength` (nm), or `base` for rays without one.
+    pub fn ior(&self, base: f64, wavelength: Option<f64>) -> f64 {
+        let Some(lambda) = wavelength else {
+            return base;
+        };
+        let l = lambda * 1e-3;
+        let l2 = l * l;
+
+        match self {
+            Self::None => base,
+            Self::Cauchy { a, b } => a + b / l2,
+            Self::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                n2.sqrt()
+            }
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum Material {
-    Lambertian { albedo: Rgb },
-    Metal { albedo: Rgb, fuzz: f64 },
-    Dielectric { refraction_index: f64 },
+    Lambertian {
+        albedo: Rgb,
+    },
+    Metal {
+        albedo: Rgb,
+        fuzz: f64,
+    },
+    /// `absorption` is the Beer–Lambert coefficient per unit distance travelled
+    /// inside the medium; black means clear glass. `refraction_index` is used
+    /// for rays without a wavelength, `dispersion` for spectral ones.
+    Dielectric {
+        refraction_index: f64,
+        absorption: Rgb,
+        dispersion: Dispersion,
+    },
+    Isotropic {
+        albedo: Rgb,
+        emission: Rgb,
+    },
+    DiffuseLight {
+        emission: Emission,
+    },
 }
 
 impl Material {
+    /// Dielectric whose interior lets through `color` after travelling `distance`.
+    pub fn tinted_dielectric(
+        refraction_index: f64,
+        color: Rgb,
+        distance: f64,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            distance > 0.0,
+            "Tinted glass needs a positive distance, not {distance}"
+        );
+        let coefficient = |c: f64| -f64::ln(c.clamp(1e-6, 1.0)) / distance;
+
+        Ok(Self::Dielectric {
+            refraction_index,
+            absorption: Rgb::new(
+                coefficient(color.r()),
+                coefficient(color.g()),
+                coefficient(color.b()),
+            ),
+            dispersion: Dispersion::None,
+        })
+    }
+
+    /// Returns the attenuation and scattered ray. In spectral renders the
+    /// attenuation holds one value per path wavelength instead of RGB.
     pub fn scatter(
         &self,
         ray: &Ray,
         hit_record: &HitRecord,
         rng: &mut impl Rng,
     ) -> Option<(Rgb, Ray)> {
+        let wavelengths = ray.wavelengths();
+
         match self {
             Self::Lambertian { albedo } => {
+                let attenuation = reflectance(*albedo, wavelengths);
                 let scatter_direction = hit_record.n + rand_unit_vec(rng);
 
                 if scatter_direction.near_zero() {
-                    Some((*albedo, Ray::new(hit_record.p, hit_record.n)))
+                    Some((attenuation, ray.spawn(hit_record.p, hit_record.n)))
                 } else {
-                    Some((*albedo, Ray::new(hit_record.p, scatter_direction)))
+                    Some((attenuation, ray.spawn(hit_record.p, scatter_direction)))
                 }
             }
 
@@ -38,20 +139,36 @@ impl Material {
                 let reflected =
                     norm(reflect(*ray.direction(), hit_record.n)) + (fuzz * rand_unit_vec(rng));
 
-                let scattered = Ray::new(hit_record.p, reflected);
+                let scattered = ray.spawn(hit_record.p, reflected);
                 if dot(*scattered.direction(), hit_record.n) > 0.0 {
-                    Some((*albedo, scattered))
+                    Some((reflectance(*albedo, wavelengths), scattered))
                 } else {
                     None
                 }
             }
 
-            Self::Dielectric { refraction_index } => {
-                let attenuation = Rgb::new(1.0, 1.0, 1.0);
+            Self::Dielectric {
+                refraction_index,
+                absorption,
+                dispersion,
+            } => {
+                // Hitting a back face means the ray travelled through the interior.
+                let mut attenuation = if hit_record.front_face {
+                    Rgb::new(1.0, 1.0, 1.0)
+                } else {
+                    let distance = hit_record.t * ray.direction().len();
+                    let transmittance = Rgb::new(
+                        f64::exp(-absorption.r() * distance),
+                        f64::exp(-absorption.g() * distance),
+                        f64::exp(-absorption.b() * distance),
+                    );
+                    reflectance(transmittance, wavelengths)
+                };
+                let ior = dispersion.ior(*refraction_index, wavelengths.map(|wl| wl.primary()));
                 let ri = if hit_record.front_face {
-                    1.0 / *refraction_index
+                    1.0 / ior
                 } else {
-                    *refraction_index
+                    ior
                 };
 
                 let unit_dir = norm(*ray.direction());
@@ -66,8 +183,89 @@ impl Material {
                     refract(unit_dir, hit_record.n, ri)
                 };
 
-                Some((attenuation, Ray::new(hit_record.p, direction)))
+                let mut scattered = ray.spawn(hit_record.p, direction);
+
+                // The path now follows the hero wavelength only, so the other two
+                // are dropped and its weight makes up for them.
+                if !matches!(dispersion, Dispersion::None)
+                    && let Some(wl) = wavelengths
+                    && !wl.is_single()
+                {
+                    attenuation = attenuation * Vec3::new(3.0, 0.0, 0.0);
+                    scattered = scattered.with_wavelengths(Some(wl.as_single()));
+                }
+
+                Some((attenuation, scattered))
+            }
+
+            Self::Isotropic { albedo, .. } => Some((
+                reflectance(*albedo, wavelengths),
+                ray.spawn(hit_record.p, rand_unit_vec(rng)),
+            )),
+
+            Self::DiffuseLight { .. } => None,
+        }
+    }
+
+    /// Whether `scatter` picks directions from a (near) delta distribution,
+    /// so paths can't be connected through this material.
+    pub fn is_specular(&self) -> bool {
+        matches!(self, Self::Metal { .. } | Self::Dielectric { .. })
+    }
+
+    /// BSDF (or phase function) value for light arriving from `wi` and
+    /// leaving towards `wo`, with `n` the normal on the side of `wo`.
+    /// Specular materials and lights return black.
+    pub fn eval(
+        &self,
+        wo: Vec3,
+        wi: Vec3,
+        n: Vec3,
+        wavelengths: Option<SampledWavelengths>,
+    ) -> Rgb {
+        match self {
+            Self::Lambertian { albedo } => {
+                if dot(wo, n) > 0.0 && dot(wi, n) > 0.0 {
+                    reflectance(*albedo, wavelengths) / std::f64::consts::PI
+                } else {
+                    Rgb::BLACK
+                }
+            }
+            Self::Isotropic { albedo, .. } => {
+                reflectance(*albedo, wavelengths) / (4.0 * std::f64::consts::PI)
             }
+            _ => Rgb::BLACK,
+        }
+    }
+
+    /// Solid angle density with which `scatter` picks `wi` for a path
+    /// arriving from `wo`. Zero for specular materials and lights.
+    pub fn pdf(&self, wo: Vec3, wi: Vec3, n: Vec3) -> f64 {
+        match self {
+            Self::Lambertian { .. } if dot(wo, n) > 0.0 => {
+                dot(norm(wi), n).max(0.0) / std::f64::consts::PI
+            }
+            Self::Isotropic { .. } => 1.0 / (4.0 * std::f64::consts::PI),
+            _ => 0.0,
+        }
+    }
+
+    /// Linear sRGB surface colour, for feature buffers and debug views.
+    pub fn albedo(&self) -> Rgb {
+        match self {
+            Self::Lambertian { albedo }
+            | Self::Metal { albedo, .. }
+            | Self::Isotropic { albedo, .. } => *albedo,
+            Self::Dielectric { .. } => Rgb::new(1.0, 1.0, 1.0),
+            Self::DiffuseLight { emission } => emission.sample(None),
+        }
+    }
+
+    pub fn emitted(&self, ray: &Ray) -> Rgb {
+        match self {
+            Self::Isotropic { emission, .. } => illuminant(*emission, ray.wavelengths()),
+            Self::DiffuseLight { emission } => emission.sample(ray.wavelengths()),
+            _ => Rgb::BLACK,
         }
     }
 }
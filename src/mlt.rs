@@ -0,0 +1,330 @@
+use rand::{Rng, RngCore, rngs::SmallRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    camera::{Camera, FilmProgress, Viewport, batches, film_luminance, progress_bar, to_film},
+    hittable::Hittable,
+    integrator::{Background, Integrator, PathTracer},
+    ray::Ray,
+    rbg::Rgb,
+    sampler::seeded_rng,
+    spectrum::SampledWavelengths,
+};
+
+/// Primary sample space Metropolis light transport (Kelemen et al. 2002).
+/// Markov chains mutate the random numbers a `PathTracer` consumes, so they
+/// linger on paths that carry a lot of light. Large steps draw fresh random
+/// numbers, small steps perturb the current ones. A bootstrap phase of
+/// independent paths estimates the image brightness for normalisation and
+/// picks the chains' starting points.
+pub struct Mlt {
+    pub path_tracer: PathTracer,
+    pub chains: usize,
+    pub bootstrap_samples: usize,
+    pub large_step_probability: f64,
+    /// Standard deviation of small step perturbations.
+    pub sigma: f64,
+}
+
+impl Mlt {
+    pub fn new(path_tracer: PathTracer) -> Self {
+        Self {
+            path_tracer,
+            chains: 1000,
+            bootstrap_samples: 100_000,
+            large_step_probability: 0.3,
+            sigma: 0.01,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PrimarySample {
+    value: f64,
+    last_modification: u64,
+    value_backup: f64,
+    modify_backup: u64,
+}
+
+/// Source of random numbers for one Markov chain. Each number drawn is a
+/// coordinate of the current point in primary sample space, mutated lazily
+/// the first time it's used in an iteration.
+struct MltSampler {
+    rng: SmallRng,
+    samples: Vec<PrimarySample>,
+    sigma: f64,
+    large_step_probability: f64,
+    large_step: bool,
+    iteration: u64,
+    last_large_step_iteration: u64,
+    index: usize,
+}
+
+impl MltSampler {
+    fn new(rng: SmallRng, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng,
+            samples: Vec::new(),
+            sigma,
+            large_step_probability,
+            large_step: true,
+            iteration: 0,
+            last_large_step_iteration: 0,
+            index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.random::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modification == self.iteration {
+                sample.value = sample.value_backup;
+                sample.last_modification = sample.modify_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// Standard normal sample (Box-Muller).
+    fn normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.rng.random::<f64>();
+        let u2 = self.rng.random::<f64>();
+        f64::sqrt(-2.0 * u1.ln()) * f64::cos(2.0 * std::f64::consts::PI * u2)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        // Coordinates the chain hasn't used before are drawn fresh, as the
+        // path tracer's rejection sampling loops expect uniform numbers.
+        if self.index >= self.samples.len() {
+            let value = self.rng.random();
+            self.samples.push(PrimarySample {
+                value,
+                last_modification: self.iteration,
+                value_backup: value,
+                modify_backup: self.iteration.saturating_sub(1),
+            });
+            self.index += 1;
+            return value;
+        }
+
+        let mut sample = self.samples[self.index];
+
+        // Catch up with a large step this coordinate missed
+        if sample.last_modification < self.last_large_step_iteration {
+            sample.value = self.rng.random();
+            sample.last_modification = self.last_large_step_iteration;
+        }
+
+        sample.value_backup = sample.value;
+        sample.modify_backup = sample.last_modification;
+
+        if self.large_step {
+            sample.value = self.rng.random();
+        } else {
+            // All the small steps missed since the last change, at once
+            let missed = (self.iteration - sample.last_modification) as f64;
+            sample.value += self.normal() * self.sigma * missed.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modification = self.iteration;
+
+        self.samples[self.index] = sample;
+        self.index += 1;
+        sample.value
+    }
+}
+
+impl RngCore for MltSampler {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_f64() * 4294967296.0) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_f64() * 18446744073709551616.0) as u64
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        for chunk in dst.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+impl Mlt {
+    /// Traces the path the sampler's current primary sample describes,
+    /// returning the pixel it passes through and its film value.
+    fn sample(
+        &self,
+        camera: &Camera,
+        view: &Viewport,
+        world: &dyn Hittable,
+        sampler: &mut MltSampler,
+    ) -> (usize, Rgb) {
+        let (width, height) = (view.width(), view.height());
+        let i = ((sampler.next_f64() * width as f64) as usize).min(width - 1);
+        let j = ((sampler.next_f64() * height as f64) as usize).min(height - 1);
+
+        let wavelengths = camera
+            .spectral
+            .then(|| SampledWavelengths::sample_visible(sampler.next_f64()));
+
+        let ray = camera.camera_ray(view, i, j, wavelengths, sampler);
+        let radiance = self
+            .path_tracer
+            .radiance(ray, world, &camera.background, sampler);
+
+        (j * width + i, to_film(radiance, wavelengths))
+    }
+}
+
+impl Integrator for Mlt {
+    /// Markov chains wander over the whole image, so the camera renders MLT
+    /// through `render_film`. A single ray gets the path traced estimate the
+    /// chains mutate.
+    fn radiance(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+    ) -> Rgb {
+        self.path_tracer.radiance(ray, world, background, rng)
+    }
+
+    fn render_film(
+        &self,
+        camera: &Camera,
+        world: &dyn Hittable,
+        progress: &mut FilmProgress,
+    ) -> Option<Vec<Rgb>> {
+        let view = camera.viewport();
+        let pixel_count = view.width() * view.height();
+        let new_sampler = |index: usize| {
+            let rng = seeded_rng(&[camera.seed, index as u64, 0]);
+            MltSampler::new(rng, self.sigma, self.large_step_probability)
+        };
+
+        // Bootstrap: independent paths, each reproducible from its index.
+        let bootstrap: Vec<f64> = (0..self.bootstrap_samples.max(1))
+            .into_par_iter()
+            .map(|index| {
+                let (_, film) = self.sample(camera, &view, world, &mut new_sampler(index));
+                film_luminance(film, camera.spectral)
+            })
+            .collect();
+
+        let total: f64 = bootstrap.iter().sum();
+        let b = total / bootstrap.len() as f64;
+        if total <= 0.0 {
+            return Some(vec![Rgb::BLACK; pixel_count]);
+        }
+
+        let cdf: Vec<f64> = bootstrap
+            .iter()
+            .scan(0.0, |sum, i| {
+                *sum += i / total;
+                Some(*sum)
+            })
+            .collect();
+
+        let chains = self.chains.max(1);
+        let total_mutations = pixel_count as u64 * camera.samples_per_pixel as u64;
+        let mutations_per_chain = total_mutations.div_ceil(chains as u64);
+
+        let bar = progress_bar(mutations_per_chain * chains as u64);
+
+        // Each chain splats onto its own film, added in chain order
+        let run_chain = |chain: usize| {
+            let mut film = vec![Rgb::BLACK; pixel_count];
+
+            // Start from a bootstrap path picked proportionally to its brightness
+            let mut rng = seeded_rng(&[camera.seed, chain as u64, 1]);
+            let u: f64 = rng.random();
+            let start = cdf.partition_point(|c| *c < u).min(cdf.len() - 1);
+
+            let mut sampler = new_sampler(start);
+            let (mut pixel, mut l) = self.sample(camera, &view, world, &mut sampler);
+            let mut y = film_luminance(l, camera.spectral);
+
+            let mut mutations = 0;
+            for mutation in 1..=mutations_per_chain {
+                if camera.cancel.is_cancelled() {
+                    break;
+                }
+                sampler.start_iteration();
+                let (proposed_pixel, proposed_l) = self.sample(camera, &view, world, &mut sampler);
+                let proposed_y = film_luminance(proposed_l, camera.spectral);
+
+                let accept = if y > 0.0 {
+                    (proposed_y / y).min(1.0)
+                } else {
+                    1.0
+                };
+
+                // Splat both states by their expected share of the time
+                if proposed_y > 0.0 {
+                    film[proposed_pixel] =
+                        film[proposed_pixel] + proposed_l * (accept / proposed_y);
+                }
+                if y > 0.0 {
+                    film[pixel] = film[pixel] + l * ((1.0 - accept) / y);
+                }
+
+                if rng.random::<f64>() < accept {
+                    (pixel, l, y) = (proposed_pixel, proposed_l, proposed_y);
+                    sampler.accept();
+                } else {
+                    sampler.reject();
+                }
+
+                if mutation % 1024 == 0 {
+                    bar.inc(1024);
+                }
+                mutations = mutation;
+            }
+
+            bar.inc(mutations_per_chain % 1024);
+            (film, mutations)
+        };
+
+        let mut film = vec![Rgb::BLACK; pixel_count];
+        // Fewer than planned once the render is cancelled
+        let mut mutations_run = 0;
+        let scaled = |film: &[Rgb], mutations_run: u64| -> Vec<Rgb> {
+            let scale = b * pixel_count as f64 / mutations_run.max(1) as f64;
+            film.iter().map(|&value| value * scale).collect()
+        };
+        let batch_size = rayon::current_num_threads();
+
+        for batch in batches(0..chains, batch_size) {
+            let chain_films: Vec<(Vec<Rgb>, u64)> = batch.into_par_iter().map(run_chain).collect();
+
+            for (chain_film, mutations) in chain_films {
+                mutations_run += mutations;
+                for (total, value) in film.iter_mut().zip(chain_film) {
+                    *total = *total + value;
+                }
+            }
+            progress.pass_done(
+                mutations_run as f64 / pixel_count as f64,
+                &scaled(&film, mutations_run),
+            );
+        }
+
+        bar.finish();
+
+        Some(scaled(&film, mutations_run))
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::{
+    camera::PassStats,
+    image::Image,
+    image_writer::{ImageWriter, PngWriter},
+};
+
+/// How long a page's event stream may go quiet before it's sent a comment,
+/// so proxies and browsers keep it open through long passes.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Page showing the image and stats, reloading the image on every update.
+const PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Render preview</title>
+<style>
+body { background: #202020; color: #d0d0d0; font: 14px sans-serif; margin: 1em; }
+img { max-width: 100%; image-rendering: pixelated; }
+</style>
+</head>
+<body>
+<p id="stats">Waiting for the render</p>
+<img id="image" src="/image.png" alt="Render preview">
+<script>
+const stats = document.getElementById("stats");
+const image = document.getElementById("image");
+const events = new EventSource("/events");
+events.onmessage = (event) => {
+  const s = JSON.parse(event.data);
+  image.src = "/image.png?" + s.version;
+  const parts = [(s.done ? "Done after " : "") + s.elapsed.toFixed(1) + " s"];
+  if (s.samples !== null) {
+    parts.push(s.samples + " spp", s.mean_samples.toFixed(1) + " spp on average");
+  }
+  if (s.noise !== null) {
+    parts.push("noise " + s.noise.toFixed(4));
+  }
+  stats.textContent = parts.join(", ");
+  if (s.done) {
+    events.close();
+  }
+};
+</script>
+</body>
+</html>
+"#;
+
+/// Latest image of a render and its stats, as served.
+struct Preview {
+    png: Arc<Vec<u8>>,
+    /// JSON object with the version, the stats of the last pass and whether
+    /// the render is done.
+    stats: Arc<String>,
+    /// Bumped on every update.
+    version: u64,
+    done: bool,
+}
+
+type Shared = Arc<(Mutex<Preview>, Condvar)>;
+
+/// Local HTTP server for watching a render converge. It serves a page at
+/// `/`, the latest image at `/image.png`, its stats at `/stats` and an event
+/// stream at `/events` with the stats of every update.
+pub struct PreviewServer {
+    shared: Shared,
+    start: Instant,
+}
+
+impl PreviewServer {
+    /// Listens on `address` in the background, serving a black `width` by
+    /// `height` image until the first update.
+    pub fn start(address: &str, width: usize, height: usize) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Unable to serve the preview on {address}"))?;
+        eprintln!("Serving the preview at http://{}/", listener.local_addr()?);
+
+        let server = Self {
+            shared: Arc::new((
+                Mutex::new(Preview {
+                    png: Arc::default(),
+                    stats: Arc::default(),
+                    version: 0,
+                    done: false,
+                }),
+                Condvar::new(),
+            )),
+            start: Instant::now(),
+        };
+        server.update(&Image::new(width, height), None, false)?;
+
+        let shared = server.shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    // Pages going away mid-response are nothing to report
+                    let _ = serve(stream, &shared);
+                });
+            }
+        });
+
+        Ok(server)
+    }
+
+    /// Serves `image` as the render so far.
+    pub fn publish(&self, image: &Image, stats: Option<&PassStats>) -> anyhow::Result<()> {
+        self.update(image, stats, false)
+    }
+
+    /// Serves `image` as the finished render.
+    pub fn finish(&self, image: &Image, stats: Option<&PassStats>) -> anyhow::Result<()> {
+        self.update(image, stats, true)
+    }
+
+    fn update(&self, image: &Image, stats: Option<&PassStats>, done: bool) -> anyhow::Result<()> {
+        let mut png = Vec::new();
+        PngWriter::new(&mut png).write(image)?;
+
+        let (preview, changed) = &*self.shared;
+        let mut preview = preview.lock().unwrap();
+        preview.version += 1;
+        preview.done = done;
+        preview.png = Arc::new(png);
+        preview.stats = Arc::new(stats_json(
+            preview.version,
+            self.start.elapsed(),
+            stats,
+            done,
+        ));
+        changed.notify_all();
+        Ok(())
+    }
+}
+
+fn stats_json(version: u64, elapsed: Duration, stats: Option<&PassStats>, done: bool) -> String {
+    // JSON has no infinity, which the noise is until pixels have 2 samples
+    let number = |value: Option<f64>| match value {
+        Some(value) if value.is_finite() => value.to_string(),
+        _ => "null".to_string(),
+    };
+    format!(
+        r#"{{"version":{version},"done":{done},"elapsed":{},"samples":{},"mean_samples":{},"noise":{}}}"#,
+        elapsed.as_secs_f64(),
+        number(stats.map(|s| s.samples as f64)),
+        number(stats.map(|s| s.mean_samples)),
+        number(stats.map(|s| s.noise)),
+    )
+}
+
+/// Answers one request on `stream`.
+fn serve(mut stream: TcpStream, shared: &Shared) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Headers don't change the answer
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = target.split('?').next().unwrap_or(target);
+    let preview = || shared.0.lock().unwrap();
+    match path {
+        "/" => respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            PAGE.as_bytes(),
+        ),
+        "/image.png" => {
+            let png = preview().png.clone();
+            respond(&mut stream, "200 OK", "image/png", &png)
+        }
+        "/stats" => {
+            let stats = preview().stats.clone();
+            respond(&mut stream, "200 OK", "application/json", stats.as_bytes())
+        }
+        "/events" => events(&mut stream, shared),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found\n"),
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> anyhow::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
+
+/// Sends the stats of every update as server-sent events, until the render
+/// is done.
+fn events(stream: &mut TcpStream, shared: &Shared) -> anyhow::Result<()> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+          Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+    )?;
+
+    let (preview, changed) = &**shared;
+    let mut sent = None;
+    loop {
+        let (stats, done) = {
+            let (preview, timeout) = changed
+                .wait_timeout_while(preview.lock().unwrap(), KEEPALIVE_INTERVAL, |preview| {
+                    sent == Some(preview.version)
+                })
+                .unwrap();
+            if timeout.timed_out() {
+                drop(preview);
+                stream.write_all(b": keepalive\n\n")?;
+                continue;
+            }
+            sent = Some(preview.version);
+            (preview.stats.clone(), preview.done)
+        };
+
+        write!(stream, "data: {stats}\n\n")?;
+        stream.flush()?;
+        if done {
+            return Ok(());
+        }
+    }
+}
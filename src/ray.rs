@@ -1,13 +1,29 @@
-use crate::{point::Point3, vec3::Vec3};
+use crate::{point::Point3, spectrum::SampledWavelengths, vec3::Vec3};
 
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    wavelengths: Option<SampledWavelengths>,
 }
 
 impl Ray {
     pub const fn new(origin: Point3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            wavelengths: None,
+        }
+    }
+
+    /// Tags the ray with the wavelengths its path carries in spectral renders.
+    pub const fn with_wavelengths(mut self, wavelengths: Option<SampledWavelengths>) -> Self {
+        self.wavelengths = wavelengths;
+        self
+    }
+
+    /// A new ray continuing the same path, keeping its wavelengths.
+    pub const fn spawn(&self, origin: Point3, direction: Vec3) -> Self {
+        Self::new(origin, direction).with_wavelengths(self.wavelengths)
     }
 
     pub fn at(&self, timestep: f64) -> Point3 {
@@ -21,4 +37,8 @@ impl Ray {
     pub fn direction(&self) -> &Vec3 {
         &self.direction
     }
+
+    pub fn wavelengths(&self) -> Option<SampledWavelengths> {
+        self.wavelengths
+    }
 }
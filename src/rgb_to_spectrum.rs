@@ -0,0 +1,124 @@
+use std::sync::LazyLock;
+
+use crate::{
+    cie::{SigmoidPolynomial, d65_normalized},
+    rbg::Rgb,
+    spectrum::SampledWavelengths,
+    vec3::Vec3,
+};
+
+// `RES`, `SCALE` and `COEFFS`, fitted by the build script
+include!(concat!(env!("OUT_DIR"), "/rgb_to_spectrum.rs"));
+
+/// Precomputed sigmoid coefficients over RGB space, indexed by the largest
+/// channel, its (non-linearly spaced) value in `SCALE` and the two other
+/// channels relative to it.
+struct CoefficientTable {
+    coeffs: Vec<[f64; 3]>,
+}
+
+static TABLE: LazyLock<CoefficientTable> = LazyLock::new(CoefficientTable::load);
+
+impl CoefficientTable {
+    fn load() -> Self {
+        let coeffs = COEFFS
+            .chunks_exact(24)
+            .map(|c| [0, 8, 16].map(|at| f64::from_le_bytes(c[at..at + 8].try_into().unwrap())))
+            .collect();
+        Self { coeffs }
+    }
+
+    fn index(l: usize, k: usize, j: usize, i: usize) -> usize {
+        ((l * RES + k) * RES + j) * RES + i
+    }
+
+    fn lookup(&self, rgb: Rgb) -> SigmoidPolynomial {
+        let channels = [rgb.r(), rgb.g(), rgb.b()].map(|c| c.clamp(0.0, 1.0));
+
+        // Grey levels have an exact constant solution.
+        if channels[0] == channels[1] && channels[1] == channels[2] {
+            let v = channels[0];
+            let c2 = if v <= 0.0 {
+                f64::NEG_INFINITY
+            } else if v >= 1.0 {
+                f64::INFINITY
+            } else {
+                (v - 0.5) / f64::sqrt(v * (1.0 - v))
+            };
+            return SigmoidPolynomial { c: [0.0, 0.0, c2] };
+        }
+
+        let l = if channels[0] > channels[1] {
+            if channels[0] > channels[2] { 0 } else { 2 }
+        } else if channels[1] > channels[2] {
+            1
+        } else {
+            2
+        };
+
+        let z = channels[l];
+        let x = channels[(l + 1) % 3] * (RES - 1) as f64 / z;
+        let y = channels[(l + 2) % 3] * (RES - 1) as f64 / z;
+
+        let xi = (x as usize).min(RES - 2);
+        let yi = (y as usize).min(RES - 2);
+        let zi = SCALE.partition_point(|s| *s <= z).clamp(1, RES - 1) - 1;
+
+        let dx = x - xi as f64;
+        let dy = y - yi as f64;
+        let dz = (z - SCALE[zi]) / (SCALE[zi + 1] - SCALE[zi]);
+
+        let mut c = [0.0; 3];
+        for (n, cn) in c.iter_mut().enumerate() {
+            let co = |dk: usize, dj: usize, di: usize| {
+                self.coeffs[Self::index(l, zi + dk, yi + dj, xi + di)][n]
+            };
+            let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+
+            *cn = lerp(
+                lerp(
+                    lerp(co(0, 0, 0), co(0, 0, 1), dx),
+                    lerp(co(0, 1, 0), co(0, 1, 1), dx),
+                    dy,
+                ),
+                lerp(
+                    lerp(co(1, 0, 0), co(1, 0, 1), dx),
+                    lerp(co(1, 1, 0), co(1, 1, 1), dx),
+                    dy,
+                ),
+                dz,
+            );
+        }
+
+        SigmoidPolynomial { c }
+    }
+}
+
+/// A reflectance as seen by a path: the RGB value itself, or its smooth
+/// spectral upsampling evaluated at the path's wavelengths.
+pub fn reflectance(rgb: Rgb, wavelengths: Option<SampledWavelengths>) -> Vec3 {
+    match wavelengths {
+        Some(wl) => {
+            let s = TABLE.lookup(rgb);
+            wl.map(|lambda| s.eval(lambda))
+        }
+        None => rgb,
+    }
+}
+
+/// Emitted radiance given as (unbounded) linear sRGB, upsampled relative to the
+/// D65 white point for spectral paths.
+pub fn illuminant(rgb: Rgb, wavelengths: Option<SampledWavelengths>) -> Vec3 {
+    let Some(wl) = wavelengths else {
+        return rgb;
+    };
+
+    let m = rgb.r().max(rgb.g()).max(rgb.b());
+    if m <= 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    let scale = 2.0 * m;
+    let s = TABLE.lookup(rgb / scale);
+    wl.map(|lambda| scale * s.eval(lambda) * d65_normalized(lambda))
+}
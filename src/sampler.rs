@@ -0,0 +1,375 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use rand::{Rng, RngCore, SeedableRng, rngs::SmallRng};
+
+/// How a `Sampler` places the samples of a pixel.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers
+    Independent,
+    /// Jittered grid per pair of dimensions, shuffled independently per pair
+    Stratified,
+    /// Halton sequence with random digit permutations per pixel
+    Halton,
+    /// Owen-scrambled Sobol points, shuffled independently per pair of dimensions
+    Sobol,
+    /// Sobol points shared by all pixels, offset by a blue-noise mask so the
+    /// remaining error looks like high frequency noise
+    BlueNoise,
+}
+
+/// Source of the random numbers for one pixel sample. Every number drawn is
+/// the next dimension of that sample, so camera rays, lens, BSDF and light
+/// sampling all consume the pattern in the order they ask for numbers.
+/// Dimensions are grouped in pairs, matching the 2D decisions most of them
+/// make.
+pub struct Sampler {
+    pub kind: SamplerKind,
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+    rng: SmallRng,
+}
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            kind,
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Moves to sample `index` of pixel `(i, j)`, starting again from the
+    /// first dimension.
+    pub fn start_pixel_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.pixel = ((j as u64) << 32) | i as u64;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = seeded_rng(&[self.seed, self.pixel, index as u64]);
+    }
+
+    /// Skips the rest of a pair after a 1D decision, so the following 2D
+    /// decisions line up with the pairs again.
+    pub fn align_to_pair(&mut self) {
+        self.dimension += self.dimension % 2;
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        let value = match self.kind {
+            SamplerKind::Independent => self.rng.random(),
+            SamplerKind::Stratified => self.stratified(dimension),
+            SamplerKind::Halton => self.halton(dimension),
+            SamplerKind::Sobol => self.sobol(dimension),
+            SamplerKind::BlueNoise => self.blue_noise(dimension),
+        };
+
+        value.min(ONE_MINUS_EPSILON)
+    }
+
+    fn pair_hash(&self, dimension: u32) -> u64 {
+        hash(&[self.seed, self.pixel, (dimension / 2) as u64])
+    }
+
+    fn stratified(&mut self, dimension: u32) -> f64 {
+        let side = (self.samples_per_pixel as f64).sqrt() as u32;
+        let pair = self.pair_hash(dimension);
+        let stratum = permutation_element(self.index, self.samples_per_pixel, pair as u32);
+
+        // Samples beyond the largest square grid aren't stratified
+        if stratum >= side * side {
+            return self.rng.random();
+        }
+
+        let cell = if dimension.is_multiple_of(2) {
+            stratum % side
+        } else {
+            stratum / side
+        };
+        let jitter = to_unit(hash(&[pair, self.index as u64, dimension as u64]));
+        (cell as f64 + jitter) / side as f64
+    }
+
+    fn halton(&mut self, dimension: u32) -> f64 {
+        let Some(&base) = PRIMES.get(dimension as usize) else {
+            // High Halton dimensions correlate badly, so they're left random
+            return self.rng.random();
+        };
+
+        let scramble = hash(&[self.seed, self.pixel, dimension as u64]);
+        scrambled_radical_inverse(base, self.index as u64, scramble)
+    }
+
+    fn sobol(&mut self, dimension: u32) -> f64 {
+        // Scrambling the index shuffles the points independently per pair,
+        // while every power of two prefix stays well distributed (Burley 2020).
+        let pair = self.pair_hash(dimension);
+        let index = owen_scramble(self.index, pair as u32);
+        let scramble = hash(&[pair, dimension as u64]) as u32;
+        to_unit_u32(owen_scramble(sobol(index, dimension % 2), scramble))
+    }
+
+    fn blue_noise(&mut self, dimension: u32) -> f64 {
+        // Same points in every pixel, so only the mask decorrelates neighbours
+        let pair = hash(&[self.seed, (dimension / 2) as u64]);
+        let index = owen_scramble(self.index, pair as u32);
+        let scramble = hash(&[pair, dimension as u64]) as u32;
+        let point = to_unit_u32(owen_scramble(sobol(index, dimension % 2), scramble));
+
+        // Toroidal shift of the mask per dimension
+        let shift = hash(&[self.seed, dimension as u64, 1]);
+        let x = (self.pixel as u32 as usize + shift as usize) % MASK_SIZE;
+        let y = ((self.pixel >> 32) as usize + (shift >> 32) as usize) % MASK_SIZE;
+
+        let value = point + blue_noise_mask()[y * MASK_SIZE + x];
+        value - value.floor()
+    }
+}
+
+impl RngCore for Sampler {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_f64() * 4294967296.0) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_f64() * 18446744073709551616.0) as u64
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        for chunk in dst.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Mixes `values` into one well distributed hash.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        mix_bits(h ^ v.wrapping_mul(0xbf58_476d_1ce4_e5b9))
+    })
+}
+
+/// Generator seeded from a render seed and the indices of what it's used
+/// for, so results don't depend on which thread asks first.
+pub fn seeded_rng(values: &[u64]) -> SmallRng {
+    SmallRng::seed_from_u64(hash(values))
+}
+
+fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+fn to_unit_u32(bits: u32) -> f64 {
+    bits as f64 * (1.0 / 4294967296.0)
+}
+
+/// Digits of `index` in `base` mirrored around the radix point, each digit
+/// position shuffled by its own permutation. Runs past the leading digits of
+/// `index`, as the permuted zero digits still matter.
+fn scrambled_radical_inverse(base: u64, mut index: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed: u64 = 0;
+    let mut position = 0;
+
+    while 1.0 - (base - 1) as f64 * inv_base_n < 1.0 {
+        let next = index / base;
+        let digit = (index - next * base) as u32;
+        let permuted = permutation_element(digit, base as u32, hash(&[seed, position]) as u32);
+
+        reversed = reversed * base + permuted as u64;
+        inv_base_n *= inv_base;
+        index = next;
+        position += 1;
+    }
+
+    reversed as f64 * inv_base_n
+}
+
+/// Component `dimension` (0 or 1) of Sobol point `index`, as 32 fractional bits.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+
+    // Direction numbers of the second dimension, from the polynomial x + 1
+    let mut v = 1 << 31;
+    let mut result = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        v ^= v >> 1;
+        index >>= 1;
+    }
+    result
+}
+
+/// Hash based Owen scrambling (Laine & Karras 2011): each bit is flipped
+/// depending on the bits above it, which keeps the points stratified.
+fn owen_scramble(mut v: u32, seed: u32) -> u32 {
+    v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+/// Element `i` of a random permutation of `0..len` picked by `seed`, without
+/// building the permutation (Kensler 2013).
+fn permutation_element(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < len {
+            break;
+        }
+    }
+
+    (i.wrapping_add(seed)) % len
+}
+
+const MASK_SIZE: usize = 64;
+
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)
+}
+
+/// Blue-noise dither mask with values in `[0, 1)`, built with Ulichney's
+/// void-and-cluster method on a torus.
+fn void_and_cluster() -> Vec<f64> {
+    const N: usize = MASK_SIZE * MASK_SIZE;
+    let sigma: f64 = 1.5;
+
+    let wrap = |d: usize| d.min(MASK_SIZE - d) as f64;
+    let kernel: Vec<f64> = (0..N)
+        .map(|k| {
+            let (dx, dy) = (wrap(k % MASK_SIZE), wrap(k / MASK_SIZE));
+            f64::exp(-(dx * dx + dy * dy) / (2.0 * sigma * sigma))
+        })
+        .collect();
+
+    // Gaussian weighted count of the points around every pixel
+    let toggle = |energy: &mut [f64], on: &mut [bool], p: usize| {
+        on[p] = !on[p];
+        let sign = if on[p] { 1.0 } else { -1.0 };
+        let (px, py) = (p % MASK_SIZE, p / MASK_SIZE);
+        for (k, e) in energy.iter_mut().enumerate() {
+            let dx = (k % MASK_SIZE + MASK_SIZE - px) % MASK_SIZE;
+            let dy = (k / MASK_SIZE + MASK_SIZE - py) % MASK_SIZE;
+            *e += sign * kernel[dy * MASK_SIZE + dx];
+        }
+    };
+    let tightest_cluster = |energy: &[f64], on: &[bool]| {
+        (0..N)
+            .filter(|&k| on[k])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |energy: &[f64], on: &[bool]| {
+        (0..N)
+            .filter(|&k| !on[k])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    let mut energy = vec![0.0; N];
+    let mut on = vec![false; N];
+    let mut rng = SmallRng::seed_from_u64(0);
+
+    let initial_count = N / 10;
+    while on.iter().filter(|&&b| b).count() < initial_count {
+        let p = rng.random_range(0..N);
+        if !on[p] {
+            toggle(&mut energy, &mut on, p);
+        }
+    }
+
+    // Spread the initial points out by moving the tightest one into the
+    // largest void until that changes nothing.
+    loop {
+        let cluster = tightest_cluster(&energy, &on);
+        toggle(&mut energy, &mut on, cluster);
+        let void = largest_void(&energy, &on);
+        toggle(&mut energy, &mut on, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; N];
+
+    // Ranks below the initial points: remove them tightest first
+    let (mut removed_energy, mut removed_on) = (energy.clone(), on.clone());
+    for r in (0..initial_count).rev() {
+        let cluster = tightest_cluster(&removed_energy, &removed_on);
+        toggle(&mut removed_energy, &mut removed_on, cluster);
+        rank[cluster] = r;
+    }
+
+    // Ranks above: fill the largest void first
+    for r in initial_count..N {
+        let void = largest_void(&energy, &on);
+        toggle(&mut energy, &mut on, void);
+        rank[void] = r;
+    }
+
+    rank.into_iter()
+        .map(|r| (r as f64 + 0.5) / N as f64)
+        .collect()
+}
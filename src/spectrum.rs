@@ -0,0 +1,242 @@
+use crate::{
+    cie::{self, CIE_Y_INTEGRAL, D65_CCT, LAMBDA_MAX, LAMBDA_MIN, integrate, sample_table},
+    rbg::Rgb,
+    vec3::Vec3,
+};
+
+/// CIE 1931 2° colour matching functions at `lambda` (nm).
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let [x, y, z] = cie::xyz(lambda);
+    Vec3::new(x, y, z)
+}
+
+/// Planck's law: spectral radiance of a blackbody at `temperature` (K) for
+/// wavelength `lambda` (nm).
+pub fn planck(lambda: f64, temperature: f64) -> f64 {
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const KB: f64 = 1.380_649e-23;
+
+    let l = lambda * 1e-9;
+    (2.0 * H * C * C) / (l.powi(5) * (f64::exp((H * C) / (l * KB * temperature)) - 1.0))
+}
+
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Rgb {
+    let [r, g, b] = cie::xyz_to_linear_srgb([xyz.x, xyz.y, xyz.z]);
+    Rgb::new(r, g, b)
+}
+
+/// Linear sRGB colour of a blackbody at `temperature` (K), normalised to unit
+/// luminance so callers control brightness separately.
+pub fn blackbody_rgb(temperature: f64) -> Rgb {
+    if temperature <= 0.0 {
+        return Rgb::BLACK;
+    }
+
+    let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        xyz = xyz + planck(lambda, temperature) * cie_xyz(lambda);
+        lambda += 5.0;
+    }
+
+    let rgb = xyz_to_linear_srgb(xyz / xyz.y);
+    Rgb::new(rgb.r().max(0.0), rgb.g().max(0.0), rgb.b().max(0.0))
+}
+
+/// Draws a wavelength (nm) from a distribution roughly following the eye's
+/// sensitivity, as in pbrt-v4's `SampleVisibleWavelengths`.
+pub fn sample_visible_wavelength(u: f64) -> f64 {
+    538.0 - 138.888889 * f64::atanh(0.85691062 - 1.82750197 * u)
+}
+
+pub fn visible_wavelength_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    0.0039398042 / f64::cosh(0.0072 * (lambda - 538.0)).powi(2)
+}
+
+/// The wavelengths carried by one camera path. Three wavelengths are traced
+/// together (hero wavelength sampling), so spectral samples fit in a `Vec3`
+/// just like RGB colours do.
+#[derive(Clone, Copy, Debug)]
+pub struct SampledWavelengths {
+    lambda: Vec3,
+    pdf: Vec3,
+    single: bool,
+}
+
+impl SampledWavelengths {
+    pub fn sample_visible(u: f64) -> Self {
+        let sample = |offset: f64| sample_visible_wavelength((u + offset) % 1.0);
+        let lambda = Vec3::new(sample(0.0), sample(1.0 / 3.0), sample(2.0 / 3.0));
+
+        Self {
+            lambda,
+            pdf: Vec3::new(
+                visible_wavelength_pdf(lambda.x),
+                visible_wavelength_pdf(lambda.y),
+                visible_wavelength_pdf(lambda.z),
+            ),
+            single: false,
+        }
+    }
+
+    /// The hero wavelength, which wavelength-dependent scattering follows.
+    pub fn primary(&self) -> f64 {
+        self.lambda.x
+    }
+
+    /// Whether the secondary wavelengths were dropped after a dispersive event.
+    pub fn is_single(&self) -> bool {
+        self.single
+    }
+
+    /// Marks the secondary wavelengths as terminated. The caller is responsible
+    /// for zeroing their throughput and scaling the primary's by 3.
+    pub fn as_single(self) -> Self {
+        Self {
+            single: true,
+            ..self
+        }
+    }
+
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Vec3 {
+        Vec3::new(f(self.lambda.x), f(self.lambda.y), f(self.lambda.z))
+    }
+
+    /// Monte Carlo estimate of the XYZ colour of `radiance`, sampled at these wavelengths.
+    pub fn radiance_to_xyz(&self, radiance: Vec3) -> Vec3 {
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+
+        for i in 0..3 {
+            if self.pdf[i] > 0.0 {
+                xyz = xyz + (radiance[i] / self.pdf[i]) * cie_xyz(self.lambda[i]);
+            }
+        }
+
+        xyz / (3.0 * *CIE_Y_INTEGRAL)
+    }
+}
+
+const FLUORESCENT_START: f64 = 380.0;
+const FLUORESCENT_STEP: f64 = 5.0;
+
+/// CIE F2 (cool white fluorescent), 380 to 780 nm in 5 nm steps.
+const FLUORESCENT_F2: [f64; 81] = [
+    1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62, 5.06, 34.98, 11.81, 6.27, 6.63,
+    6.93, 7.19, 7.40, 7.54, 7.62, 7.65, 7.62, 7.62, 7.45, 7.28, 7.15, 7.05, 7.04, 7.16, 7.47, 8.04,
+    8.88, 10.01, 24.88, 16.64, 14.59, 16.16, 17.56, 18.62, 21.47, 22.79, 19.29, 18.66, 17.73,
+    16.54, 15.21, 13.80, 12.36, 10.95, 9.65, 8.40, 7.32, 6.31, 5.43, 4.68, 4.02, 3.45, 2.96, 2.55,
+    2.19, 1.89, 1.64, 1.53, 1.27, 1.10, 0.99, 0.88, 0.76, 0.68, 0.61, 0.56, 0.54, 0.51, 0.47, 0.47,
+    0.43, 0.46, 0.47, 0.40, 0.33, 0.27,
+];
+
+/// CIE F7 (broadband daylight fluorescent), 380 to 780 nm in 5 nm steps.
+const FLUORESCENT_F7: [f64; 81] = [
+    2.56, 3.18, 3.84, 4.53, 6.15, 19.37, 7.37, 7.05, 7.71, 8.41, 9.15, 44.14, 17.52, 11.35, 12.00,
+    12.58, 13.08, 13.45, 13.71, 13.88, 13.95, 13.93, 13.82, 13.64, 13.43, 13.25, 13.08, 12.93,
+    12.78, 12.60, 12.44, 12.33, 12.26, 29.52, 17.05, 12.44, 12.58, 12.72, 12.83, 15.46, 16.75,
+    12.83, 12.67, 12.45, 12.19, 11.89, 11.60, 11.35, 11.12, 10.95, 10.76, 10.42, 10.11, 10.04,
+    10.02, 10.11, 9.87, 8.65, 7.27, 6.44, 5.83, 5.41, 5.04, 4.57, 4.12, 3.77, 3.46, 3.08, 2.73,
+    2.47, 2.25, 2.06, 1.90, 1.75, 1.62, 1.54, 1.45, 1.32, 1.17, 0.99, 0.81,
+];
+
+/// CIE F11 (narrow tri-band fluorescent), 380 to 780 nm in 5 nm steps.
+const FLUORESCENT_F11: [f64; 81] = [
+    0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33, 4.49, 33.94, 12.13, 6.95, 7.19,
+    7.12, 6.72, 6.13, 5.46, 4.79, 5.66, 14.29, 14.96, 8.97, 4.72, 2.33, 1.47, 1.10, 0.89, 0.83,
+    1.18, 4.90, 39.59, 72.84, 32.61, 7.52, 2.83, 1.96, 1.67, 4.43, 11.28, 14.76, 12.73, 9.74, 7.33,
+    9.72, 55.27, 42.58, 13.18, 13.16, 12.26, 5.11, 2.07, 2.34, 3.58, 3.01, 2.48, 2.14, 1.54, 1.33,
+    1.46, 1.94, 2.00, 1.20, 1.35, 4.10, 5.58, 2.51, 0.57, 0.27, 0.23, 0.21, 0.24, 0.24, 0.20, 0.24,
+    0.32, 0.26, 0.16, 0.12, 0.09,
+];
+
+#[derive(Clone, Copy, Debug)]
+pub enum Fluorescent {
+    F2,
+    F7,
+    F11,
+}
+
+/// Spectral power distribution of a light source, in arbitrary units.
+#[derive(Clone, Copy, Debug)]
+pub enum Spd {
+    Blackbody {
+        temperature: f64,
+    },
+    /// CIE D-series daylight at a correlated colour temperature (4000 K to 25000 K).
+    Daylight {
+        cct: f64,
+    },
+    /// CIE standard illuminant A (tungsten filament).
+    IlluminantA,
+    Fluorescent(Fluorescent),
+}
+
+impl Spd {
+    pub const D65: Self = Self::Daylight { cct: D65_CCT };
+
+    pub fn eval(&self, lambda: f64) -> f64 {
+        match self {
+            Self::Blackbody { temperature } => planck(lambda, *temperature),
+            Self::Daylight { cct } => cie::daylight(*cct, lambda),
+            Self::IlluminantA => {
+                let c2 = 1.435e7;
+                100.0 * (560.0 / lambda).powi(5) * (f64::exp(c2 / (2848.0 * 560.0)) - 1.0)
+                    / (f64::exp(c2 / (2848.0 * lambda)) - 1.0)
+            }
+            Self::Fluorescent(f) => {
+                let table: &[f64] = match f {
+                    Fluorescent::F2 => &FLUORESCENT_F2,
+                    Fluorescent::F7 => &FLUORESCENT_F7,
+                    Fluorescent::F11 => &FLUORESCENT_F11,
+                };
+                sample_table(table, FLUORESCENT_START, FLUORESCENT_STEP, lambda)
+            }
+        }
+    }
+
+    pub fn xyz(&self) -> Vec3 {
+        let x = integrate(|lambda| self.eval(lambda) * cie_xyz(lambda).x);
+        let y = integrate(|lambda| self.eval(lambda) * cie_xyz(lambda).y);
+        let z = integrate(|lambda| self.eval(lambda) * cie_xyz(lambda).z);
+
+        Vec3::new(x, y, z) / *CIE_Y_INTEGRAL
+    }
+}
+
+/// Emitted radiance of a light: an SPD scaled to a chosen luminance, with its
+/// linear sRGB equivalent cached for RGB renders.
+#[derive(Clone, Copy, Debug)]
+pub struct Emission {
+    spd: Spd,
+    scale: f64,
+    rgb: Rgb,
+}
+
+impl Emission {
+    pub fn new(spd: Spd, luminance: f64) -> Self {
+        let xyz = spd.xyz();
+        let scale = luminance / xyz.y;
+
+        Self {
+            spd,
+            scale,
+            rgb: xyz_to_linear_srgb(xyz * scale),
+        }
+    }
+
+    /// Radiance as seen by a path: RGB, or the SPD at the path's wavelengths.
+    pub fn sample(&self, wavelengths: Option<SampledWavelengths>) -> Vec3 {
+        match wavelengths {
+            Some(wl) => wl.map(|lambda| self.scale * self.spd.eval(lambda)),
+            None => self.rgb,
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use rand::{Rng, RngCore};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+
+use crate::{
+    camera::{Camera, FilmProgress, batches, progress_bar, to_film},
+    hittable::Hittable,
+    integrator::{Background, Integrator, PathTracer},
+    material::Material,
+    point::Point3,
+    ray::Ray,
+    rbg::Rgb,
+    sampler::seeded_rng,
+    spectrum::SampledWavelengths,
+    vec3::{Vec3, dot, norm, rand_unit_vec},
+};
+
+/// Stochastic progressive photon mapping (Hachisuka & Jensen 2009). Each
+/// iteration traces one camera path per pixel up to its first diffuse
+/// surface, then shoots photons from the area lights and gathers those
+/// landing near each of these visible points. The gather radius shrinks
+/// every iteration, so the estimate converges while caustics and
+/// specular-diffuse-specular paths come out smooth.
+///
+/// Light from the background and from emissive volumes only reaches the
+/// image directly or through specular bounces.
+pub struct Sppm {
+    pub max_depth: i32,
+    /// Gather radius for the first iteration, in scene units.
+    pub initial_radius: f64,
+    /// Photons shot per iteration; one per pixel when `None`.
+    pub photons_per_iteration: Option<usize>,
+}
+
+impl Sppm {
+    pub fn new(max_depth: i32, initial_radius: f64) -> Self {
+        Self {
+            max_depth,
+            initial_radius,
+            photons_per_iteration: None,
+        }
+    }
+}
+
+/// First diffuse hit of a camera path.
+struct VisiblePoint {
+    p: Point3,
+    n: Vec3,
+    wo: Vec3,
+    mat: Material,
+    /// Camera path throughput up to here.
+    beta: Rgb,
+    single: bool,
+}
+
+/// Per-pixel state carried across iterations. `direct` and `tau` are film values.
+struct SppmPixel {
+    radius: f64,
+    /// Light reaching the camera without a diffuse bounce, summed over iterations.
+    direct: Rgb,
+    vp: Option<VisiblePoint>,
+    /// Photon count so far, reduced by the radius shrinkage.
+    n: f64,
+    /// Flux gathered so far, scaled to the current radius.
+    tau: Rgb,
+}
+
+/// Photons traced in parallel before their flux is added to the visible points.
+const PHOTON_BATCH: usize = 4096;
+
+fn is_diffuse(mat: &Material) -> bool {
+    matches!(mat, Material::Lambertian { .. })
+}
+
+/// Uniform grid over visible points, with cells as large as the largest radius
+/// so each point is stored in at most 8 of them.
+struct VisiblePointGrid {
+    cell_size: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl VisiblePointGrid {
+    fn build(pixels: &[SppmPixel]) -> Self {
+        let cell_size = pixels
+            .iter()
+            .filter(|px| px.vp.is_some())
+            .map(|px| px.radius)
+            .fold(0.0, f64::max);
+
+        let mut grid = Self {
+            cell_size,
+            cells: HashMap::new(),
+        };
+
+        for (idx, px) in pixels.iter().enumerate() {
+            let Some(vp) = &px.vp else {
+                continue;
+            };
+
+            let r = Vec3::new(px.radius, px.radius, px.radius);
+            let lo = grid.cell(vp.p - r);
+            let hi = grid.cell(vp.p + r);
+
+            for x in lo[0]..=hi[0] {
+                for y in lo[1]..=hi[1] {
+                    for z in lo[2]..=hi[2] {
+                        grid.cells.entry([x, y, z]).or_default().push(idx);
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    fn cell(&self, p: Point3) -> [i64; 3] {
+        [0, 1, 2].map(|axis| (p[axis] / self.cell_size).floor() as i64)
+    }
+
+    fn candidates(&self, p: Point3) -> &[usize] {
+        if self.cell_size <= 0.0 {
+            return &[];
+        }
+
+        self.cells.get(&self.cell(p)).map_or(&[], |v| v.as_slice())
+    }
+}
+
+impl Sppm {
+    /// Follows a camera path through specular bounces and media, recording
+    /// the light it picks up on the way and where it first hits a diffuse
+    /// surface.
+    fn trace_camera_path(
+        &self,
+        mut ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        mut rng: &mut dyn RngCore,
+        pixel: &mut SppmPixel,
+    ) {
+        let wavelengths = ray.wavelengths();
+        let mut beta = Rgb::new(1.0, 1.0, 1.0);
+        pixel.vp = None;
+
+        for _ in 0..self.max_depth {
+            let Some(h) = world.hit(&ray, 0.00001..f64::INFINITY) else {
+                pixel.direct = pixel.direct + to_film(beta * background.color(&ray), wavelengths);
+                return;
+            };
+
+            pixel.direct = pixel.direct + to_film(beta * h.mat.emitted(&ray), wavelengths);
+
+            if is_diffuse(&h.mat) {
+                pixel.vp = Some(VisiblePoint {
+                    p: h.p,
+                    n: h.n,
+                    wo: -norm(*ray.direction()),
+                    mat: h.mat,
+                    beta,
+                    single: ray.wavelengths().is_some_and(|wl| wl.is_single()),
+                });
+                return;
+            }
+
+            let Some((attenuation, new_ray)) = h.mat.scatter(&ray, &h, &mut rng) else {
+                return;
+            };
+            beta = beta * attenuation;
+            ray = new_ray;
+        }
+    }
+
+    /// Shoots one photon from a random area light, recording the flux it
+    /// leaves at the visible points around every diffuse surface it hits.
+    fn trace_photon(
+        &self,
+        world: &dyn Hittable,
+        wavelengths: Option<SampledWavelengths>,
+        pixels: &[SppmPixel],
+        grid: &VisiblePointGrid,
+        mut rng: &mut dyn RngCore,
+    ) -> Vec<(usize, Rgb)> {
+        let mut deposits = Vec::new();
+        let Some(sample) = world.sample_emitter(rng) else {
+            return deposits;
+        };
+
+        let mut dir = sample.n + rand_unit_vec(&mut rng);
+        if dir.near_zero() {
+            dir = sample.n;
+        }
+
+        // Cosine-weighted emission, so only the area density is left to divide by
+        let mut ray = Ray::new(sample.p, norm(dir)).with_wavelengths(wavelengths);
+        let mut beta = sample.mat.emitted(&ray) * (std::f64::consts::PI / sample.pdf);
+
+        for _ in 0..self.max_depth {
+            let Some(h) = world.hit(&ray, 0.00001..f64::INFINITY) else {
+                break;
+            };
+
+            if is_diffuse(&h.mat) {
+                let wi = -norm(*ray.direction());
+                let photon_single = ray.wavelengths().is_some_and(|wl| wl.is_single());
+
+                for &idx in grid.candidates(h.p) {
+                    let px = &pixels[idx];
+                    let Some(vp) = &px.vp else {
+                        continue;
+                    };
+                    if (vp.p - h.p).len_sqrd() > px.radius * px.radius || dot(vp.n, h.n) <= 0.0 {
+                        continue;
+                    }
+
+                    let mut flux = beta * vp.mat.eval(vp.wo, wi, vp.n, wavelengths);
+                    // Both halves dropped the secondary wavelengths and made up for them.
+                    if vp.single && photon_single {
+                        flux = flux / 3.0;
+                    }
+
+                    deposits.push((idx, flux));
+                }
+            }
+
+            let Some((attenuation, new_ray)) = h.mat.scatter(&ray, &h, &mut rng) else {
+                break;
+            };
+            beta = beta * attenuation;
+            ray = new_ray;
+        }
+
+        deposits
+    }
+}
+
+impl Integrator for Sppm {
+    /// Photon mapping needs every pixel's visible point before shooting
+    /// photons, so the camera renders SPPM through `render_film`. A single
+    /// ray has no photons to gather and gets a path traced estimate of the
+    /// same light instead.
+    fn radiance(
+        &self,
+        ray: Ray,
+        world: &dyn Hittable,
+        background: &Background,
+        rng: &mut dyn RngCore,
+    ) -> Rgb {
+        PathTracer::new(self.max_depth).radiance(ray, world, background, rng)
+    }
+
+    fn render_film(
+        &self,
+        camera: &Camera,
+        world: &dyn Hittable,
+        progress: &mut FilmProgress,
+    ) -> Option<Vec<Rgb>> {
+        let view = camera.viewport();
+        let (width, height) = (view.width(), view.height());
+        let iterations = camera.samples_per_pixel.max(1);
+        let photons = self.photons_per_iteration.unwrap_or(width * height);
+
+        let mut pixels: Vec<SppmPixel> = (0..width * height)
+            .map(|_| SppmPixel {
+                radius: self.initial_radius,
+                direct: Rgb::BLACK,
+                vp: None,
+                n: 0.0,
+                tau: Rgb::BLACK,
+            })
+            .collect();
+        // Photon flux and count gathered by each visible point this iteration
+        let mut gathered = vec![(Rgb::BLACK, 0u64); width * height];
+
+        // Film values after `completed` iterations
+        let film = |pixels: &[SppmPixel], completed: usize| -> Vec<Rgb> {
+            let total_photons = (completed * photons) as f64;
+            pixels
+                .iter()
+                .map(|px| {
+                    let area = std::f64::consts::PI * px.radius * px.radius;
+                    px.direct / completed as f64 + px.tau / (total_photons * area)
+                })
+                .collect()
+        };
+
+        let bar = progress_bar((width * height) as u64 * iterations as u64);
+        let mut completed = 0;
+        for iteration in 0..iterations as u64 {
+            if camera.cancel.is_cancelled() {
+                break;
+            }
+
+            // Camera and photon paths of an iteration share their wavelengths,
+            // so gathered flux and visible point throughput can be multiplied.
+            let wavelengths = camera.spectral.then(|| {
+                let mut rng = seeded_rng(&[camera.seed, iteration]);
+                SampledWavelengths::sample_visible(rng.random())
+            });
+
+            pixels.par_iter_mut().enumerate().for_each(|(idx, px)| {
+                let mut rng = seeded_rng(&[camera.seed, iteration, idx as u64, 0]);
+                let ray = camera.camera_ray(&view, idx % width, idx / width, wavelengths, &mut rng);
+                self.trace_camera_path(ray, world, &camera.background, &mut rng, px);
+            });
+
+            let grid = VisiblePointGrid::build(&pixels);
+
+            // Deposits are added in photon order
+            for batch in batches(0..photons, PHOTON_BATCH) {
+                let deposits: Vec<_> = batch
+                    .into_par_iter()
+                    .map(|photon| {
+                        let mut rng = seeded_rng(&[camera.seed, iteration, photon as u64, 1]);
+                        self.trace_photon(world, wavelengths, &pixels, &grid, &mut rng)
+                    })
+                    .collect();
+
+                for (idx, flux) in deposits.into_iter().flatten() {
+                    gathered[idx].0 = gathered[idx].0 + flux;
+                    gathered[idx].1 += 1;
+                }
+            }
+
+            // Progressive radius reduction: keep a fraction of the new photons
+            // and shrink the radius so the density estimate stays consistent.
+            let alpha = 2.0 / 3.0;
+            pixels
+                .par_iter_mut()
+                .zip(&mut gathered)
+                .for_each(|(px, g)| {
+                    let (phi, m) = std::mem::replace(g, (Rgb::BLACK, 0));
+
+                    let Some(vp) = &px.vp else {
+                        return;
+                    };
+                    if m == 0 {
+                        return;
+                    }
+
+                    let n_new = px.n + alpha * m as f64;
+                    let r_new = px.radius * f64::sqrt(n_new / (px.n + m as f64));
+                    let shrink = (r_new * r_new) / (px.radius * px.radius);
+
+                    px.tau = (px.tau + to_film(vp.beta * phi, wavelengths)) * shrink;
+                    px.n = n_new;
+                    px.radius = r_new;
+                });
+
+            bar.inc((width * height) as u64);
+            completed += 1;
+            progress.pass_done(completed as f64, &film(&pixels, completed));
+        }
+
+        bar.finish();
+
+        // A cancelled render is normalized by the iterations it completed
+        Some(film(&pixels, completed.max(1)))
+    }
+}
@@ -0,0 +1,201 @@
+use clap::ValueEnum;
+
+/// Order in which the tiles of an image are rendered.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TileOrder {
+    /// Row by row from the top left
+    Scanline,
+    /// Outwards from the centre, where the subject usually is
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other
+    Hilbert,
+}
+
+/// Rectangle of pixels rendered together.
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    /// Indices of the tile's pixels in an image `image_width` pixels wide, row
+    /// by row.
+    pub fn pixels(&self, image_width: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| y * image_width + x))
+    }
+
+    /// The tile grown by `margin` pixels on every side, as far as a `width`
+    /// by `height` image goes.
+    pub fn grow(&self, margin: usize, width: usize, height: usize) -> Tile {
+        let (x, y) = (self.x.saturating_sub(margin), self.y.saturating_sub(margin));
+        Tile {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(width) - x,
+            height: (self.y + self.height + margin).min(height) - y,
+        }
+    }
+}
+
+/// Splits `area` of an image into tiles of at most `size` pixels square, in
+/// the given order.
+pub fn tiles(area: &Tile, size: usize, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let (columns, rows) = (area.width.div_ceil(size), area.height.div_ceil(size));
+
+    let tile = |(column, row): (usize, usize)| {
+        let (x, y) = (column * size, row * size);
+        Tile {
+            x: area.x + x,
+            y: area.y + y,
+            width: size.min(area.width - x),
+            height: size.min(area.height - y),
+        }
+    };
+
+    let grid = (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row)));
+    match order {
+        TileOrder::Scanline => grid.map(tile).collect(),
+        TileOrder::Spiral => spiral(columns, rows).into_iter().map(tile).collect(),
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            let mut grid: Vec<_> = grid.collect();
+            grid.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+            grid.into_iter().map(tile).collect()
+        }
+    }
+}
+
+/// Cells of a `columns` by `rows` grid, walking a square spiral outwards
+/// from the centre.
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let mut cells = Vec::with_capacity(columns * rows);
+    if columns == 0 || rows == 0 {
+        return cells;
+    }
+    let (mut x, mut y) = (((columns - 1) / 2) as isize, ((rows - 1) / 2) as isize);
+    // Right, down, left, up, with legs growing by one every other turn
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 1;
+    let mut turn = 0;
+
+    while cells.len() < columns * rows {
+        let (dx, dy) = directions[turn % 4];
+        for _ in 0..leg {
+            if (0..columns as isize).contains(&x) && (0..rows as isize).contains(&y) {
+                cells.push((x as usize, y as usize));
+            }
+            x += dx;
+            y += dy;
+        }
+
+        turn += 1;
+        if turn.is_multiple_of(2) {
+            leg += 1;
+        }
+    }
+
+    cells
+}
+
+/// Position of cell `(x, y)` along the Hilbert curve filling a `side` by
+/// `side` grid, with `side` a power of two.
+fn hilbert_index(side: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = side / 2;
+
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        index += s * s * ((3 * rx) ^ ry);
+
+        // Rotate the quadrant so the curve inside it starts where it enters
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks `tiles` covers every pixel of `area` exactly once.
+    fn assert_covers(area: &Tile, tiles: &[Tile]) {
+        let image_width = area.x + area.width;
+        let mut covered = vec![0; image_width * (area.y + area.height)];
+        for tile in tiles {
+            for idx in tile.pixels(image_width) {
+                covered[idx] += 1;
+            }
+        }
+        for (idx, count) in covered.iter().enumerate() {
+            let (x, y) = (idx % image_width, idx / image_width);
+            let inside = (area.x..area.x + area.width).contains(&x)
+                && (area.y..area.y + area.height).contains(&y);
+            assert_eq!(*count, usize::from(inside), "pixel ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn every_order_covers_the_area_once() {
+        let area = Tile {
+            x: 3,
+            y: 2,
+            width: 37,
+            height: 21,
+        };
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            for size in [1, 4, 16, 64] {
+                assert_covers(&area, &tiles(&area, size, order));
+            }
+        }
+    }
+
+    #[test]
+    fn spiral_visits_every_cell_once() {
+        for (columns, rows) in [(1, 1), (1, 5), (6, 1), (4, 4), (7, 3), (2, 9)] {
+            let mut cells = spiral(columns, rows);
+            assert_eq!(cells[0], ((columns - 1) / 2, (rows - 1) / 2));
+            cells.sort();
+            let grid: Vec<_> = (0..columns)
+                .flat_map(|x| (0..rows).map(move |y| (x, y)))
+                .collect();
+            assert_eq!(cells, grid, "{columns}x{rows}");
+        }
+        assert!(spiral(0, 3).is_empty());
+    }
+
+    #[test]
+    fn hilbert_curve_visits_every_cell_once_in_steps_of_one() {
+        for side in [1, 2, 4, 8, 16] {
+            let mut cells: Vec<_> = (0..side)
+                .flat_map(|x| (0..side).map(move |y| (x, y)))
+                .collect();
+            cells.sort_by_key(|&(x, y)| hilbert_index(side, x, y));
+
+            let mut indices: Vec<_> = cells
+                .iter()
+                .map(|&(x, y)| hilbert_index(side, x, y))
+                .collect();
+            indices.dedup();
+            assert_eq!(indices, (0..side * side).collect::<Vec<_>>());
+
+            for pair in cells.windows(2) {
+                let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+                assert_eq!(x0.abs_diff(x1) + y0.abs_diff(y1), 1, "side {side}");
+            }
+        }
+    }
+}
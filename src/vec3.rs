@@ -252,6 +252,19 @@ impl ops::Neg for Vec3 {
 // Helpers
 // -------------------------------------
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &Self::Output {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis out of range: {axis}"),
+        }
+    }
+}
+
 impl Display for Vec3 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "({},{})", self.x, self.y)
@@ -262,27 +275,29 @@ pub fn lerp(start_value: &Vec3, end_value: &Vec3, t: f64) -> Vec3 {
     ((1.0 - t) * start_value) + (t * end_value)
 }
 
+/// Uniformly distributed direction, mapped from exactly two random numbers
+/// so stratified samplers keep their structure.
 pub fn rand_unit_vec(rng: &mut impl Rng) -> Vec3 {
-    loop {
-        let p = Vec3::rand_in_range(rng, -1.0, 1.0);
-        let lensq = p.len_sqrd();
-
-        if (1e-160 < lensq) && (lensq <= 1.0) {
-            return p / f64::sqrt(lensq);
-        }
-    }
+    let z = 1.0 - 2.0 * rng.random::<f64>();
+    let r = f64::sqrt((1.0 - z * z).max(0.0));
+    let phi = 2.0 * std::f64::consts::PI * rng.random::<f64>();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
+/// Uniform point in the unit disk, from Shirley and Chiu's concentric mapping
+/// of two random numbers.
 pub fn rand_in_unit_disk(rng: &mut impl Rng) -> Vec3 {
-    loop {
-        let p = Vec3::new(
-            rng.random_range(-1.0..1.0),
-            rng.random_range(-1.0..1.0),
-            0.0,
-        );
-
-        if p.len_sqrd() < 1.0 {
-            return p;
-        }
+    let a = 2.0 * rng.random::<f64>() - 1.0;
+    let b = 2.0 * rng.random::<f64>() - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
     }
+
+    let quarter_pi = std::f64::consts::FRAC_PI_4;
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, quarter_pi * (b / a))
+    } else {
+        (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
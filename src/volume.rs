@@ -0,0 +1,436 @@
+use std::{fs, ops, path::Path};
+
+use anyhow::{Context, bail, ensure};
+use rand::{Rng, rngs::SmallRng};
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    point::Point3,
+    ray::Ray,
+    rbg::Rgb,
+    sampler::seeded_rng,
+    spectrum::blackbody_rgb,
+    vec3::{Vec3, norm},
+};
+
+const BRICK_SIZE: usize = 8;
+const BRICK_VOXELS: usize = BRICK_SIZE * BRICK_SIZE * BRICK_SIZE;
+
+enum Storage {
+    Dense(Vec<f32>),
+    /// VDB-style sparse storage: the grid is split into 8^3 bricks and only
+    /// bricks containing non-zero voxels are allocated.
+    Sparse {
+        brick_dims: [usize; 3],
+        bricks: Vec<Option<Box<[f32]>>>,
+    },
+}
+
+/// Sampling interpolates between neighbouring voxels, so grids need at least
+/// one along every axis.
+fn check_dims(dims: [usize; 3]) -> anyhow::Result<()> {
+    ensure!(
+        dims.iter().all(|&d| d > 0),
+        "Voxel grid is {}x{}x{}, every dimension must be at least 1",
+        dims[0],
+        dims[1],
+        dims[2]
+    );
+    Ok(())
+}
+
+/// Scalar voxel grid covering the unit cube, sampled with trilinear interpolation.
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    storage: Storage,
+    max_value: f64,
+}
+
+impl VoxelGrid {
+    pub fn dense(dims: [usize; 3], data: Vec<f32>) -> anyhow::Result<Self> {
+        check_dims(dims)?;
+        ensure!(
+            data.len() == dims[0] * dims[1] * dims[2],
+            "Voxel data has {} values but the grid is {}x{}x{}",
+            data.len(),
+            dims[0],
+            dims[1],
+            dims[2]
+        );
+
+        let max_value = data.iter().fold(0.0f32, |acc, v| acc.max(*v)) as f64;
+
+        Ok(Self {
+            dims,
+            storage: Storage::Dense(data),
+            max_value,
+        })
+    }
+
+    pub fn sparse(
+        dims: [usize; 3],
+        voxels: impl IntoIterator<Item = ([usize; 3], f32)>,
+    ) -> anyhow::Result<Self> {
+        check_dims(dims)?;
+        let brick_dims = dims.map(|d| d.div_ceil(BRICK_SIZE));
+        let mut bricks: Vec<Option<Box<[f32]>>> =
+            vec![None; brick_dims[0] * brick_dims[1] * brick_dims[2]];
+        let mut max_value: f64 = 0.0;
+
+        for ([i, j, k], value) in voxels {
+            ensure!(
+                i < dims[0] && j < dims[1] && k < dims[2],
+                "Voxel ({i}, {j}, {k}) lies outside the {}x{}x{} grid",
+                dims[0],
+                dims[1],
+                dims[2]
+            );
+
+            let brick_idx = (i / BRICK_SIZE)
+                + brick_dims[0] * ((j / BRICK_SIZE) + brick_dims[1] * (k / BRICK_SIZE));
+            let local_idx =
+                (i % BRICK_SIZE) + BRICK_SIZE * ((j % BRICK_SIZE) + BRICK_SIZE * (k % BRICK_SIZE));
+
+            let brick =
+                bricks[brick_idx].get_or_insert_with(|| vec![0.0; BRICK_VOXELS].into_boxed_slice());
+            brick[local_idx] = value;
+            max_value = max_value.max(value as f64);
+        }
+
+        Ok(Self {
+            dims,
+            storage: Storage::Sparse { brick_dims, bricks },
+            max_value,
+        })
+    }
+
+    /// Builds a dense grid by evaluating `f` at every voxel centre, given in
+    /// normalised `[0, 1]^3` coordinates.
+    pub fn from_fn(dims: [usize; 3], f: impl Fn(Vec3) -> f64) -> Self {
+        let mut data = Vec::with_capacity(dims[0] * dims[1] * dims[2]);
+
+        for k in 0..dims[2] {
+            for j in 0..dims[1] {
+                for i in 0..dims[0] {
+                    let uvw = Vec3::new(
+                        (i as f64 + 0.5) / dims[0] as f64,
+                        (j as f64 + 0.5) / dims[1] as f64,
+                        (k as f64 + 0.5) / dims[2] as f64,
+                    );
+                    data.push(f(uvw) as f32);
+                }
+            }
+        }
+
+        Self::dense(dims, data).expect("from_fn produces exactly one value per voxel")
+    }
+
+    /// Loads a headerless grid of little-endian `f32` values, x varying fastest.
+    pub fn load_raw(path: &Path, dims: [usize; 3]) -> anyhow::Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Unable to read raw volume : {}", path.to_string_lossy()))?;
+
+        ensure!(
+            bytes.len() % 4 == 0,
+            "Raw volume size is not a multiple of 4 bytes"
+        );
+
+        let data = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        Self::dense(dims, data)
+    }
+
+    pub fn max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    pub fn voxel(&self, i: usize, j: usize, k: usize) -> f64 {
+        let value = match &self.storage {
+            Storage::Dense(data) => data[i + self.dims[0] * (j + self.dims[1] * k)],
+            Storage::Sparse { brick_dims, bricks } => {
+                let brick_idx = (i / BRICK_SIZE)
+                    + brick_dims[0] * ((j / BRICK_SIZE) + brick_dims[1] * (k / BRICK_SIZE));
+                match &bricks[brick_idx] {
+                    Some(brick) => {
+                        brick[(i % BRICK_SIZE)
+                            + BRICK_SIZE * ((j % BRICK_SIZE) + BRICK_SIZE * (k % BRICK_SIZE))]
+                    }
+                    None => 0.0,
+                }
+            }
+        };
+
+        value as f64
+    }
+
+    /// Trilinearly interpolated value at normalised coordinates `uvw`.
+    pub fn sample(&self, uvw: Vec3) -> f64 {
+        let mut base = [0usize; 3];
+        let mut next = [0usize; 3];
+        let mut frac = [0.0; 3];
+
+        for axis in 0..3 {
+            let n = self.dims[axis];
+            let x = (uvw[axis] * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            base[axis] = x.floor() as usize;
+            next[axis] = (base[axis] + 1).min(n - 1);
+            frac[axis] = x - base[axis] as f64;
+        }
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let v = |i: usize, j: usize, k: usize| self.voxel(i, j, k);
+
+        let c00 = lerp(
+            v(base[0], base[1], base[2]),
+            v(next[0], base[1], base[2]),
+            frac[0],
+        );
+        let c10 = lerp(
+            v(base[0], next[1], base[2]),
+            v(next[0], next[1], base[2]),
+            frac[0],
+        );
+        let c01 = lerp(
+            v(base[0], base[1], next[2]),
+            v(next[0], base[1], next[2]),
+            frac[0],
+        );
+        let c11 = lerp(
+            v(base[0], next[1], next[2]),
+            v(next[0], next[1], next[2]),
+            frac[0],
+        );
+
+        lerp(lerp(c00, c10, frac[1]), lerp(c01, c11, frac[1]), frac[2])
+    }
+}
+
+type SparseVoxel = ([usize; 3], f32);
+
+/// The grids read from a volume file.
+pub struct VolumeGrids {
+    pub density: VoxelGrid,
+    pub emission: Option<VoxelGrid>,
+    pub temperature: Option<VoxelGrid>,
+}
+
+/// Loads a sparse text volume. The format is a `dims NX NY NZ` line followed by
+/// one or more `grid <density|emission|temperature>` sections, each listing
+/// `i j k value` voxels. Lines starting with `#` are ignored.
+pub fn load_volume_file(path: &Path) -> anyhow::Result<VolumeGrids> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read volume file : {}", path.to_string_lossy()))?;
+
+    let mut dims: Option<[usize; 3]> = None;
+    let mut sections: Vec<(String, Vec<SparseVoxel>)> = Vec::new();
+
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let parse_err = || format!("Malformed line {} in volume file", line_no + 1);
+
+        match fields.as_slice() {
+            ["dims", x, y, z] => {
+                dims = Some([
+                    x.parse().with_context(parse_err)?,
+                    y.parse().with_context(parse_err)?,
+                    z.parse().with_context(parse_err)?,
+                ]);
+            }
+            ["grid", name] => sections.push((name.to_string(), Vec::new())),
+            [i, j, k, value] => {
+                let Some((_, voxels)) = sections.last_mut() else {
+                    bail!("Voxel on line {} appears before any grid", line_no + 1);
+                };
+                voxels.push((
+                    [
+                        i.parse().with_context(parse_err)?,
+                        j.parse().with_context(parse_err)?,
+                        k.parse().with_context(parse_err)?,
+                    ],
+                    value.parse().with_context(parse_err)?,
+                ));
+            }
+            _ => bail!(parse_err()),
+        }
+    }
+
+    let Some(dims) = dims else {
+        bail!("Volume file is missing its dims line");
+    };
+
+    let mut density = None;
+    let mut emission = None;
+    let mut temperature = None;
+
+    for (name, voxels) in sections {
+        let slot = match name.as_str() {
+            "density" => &mut density,
+            "emission" => &mut emission,
+            "temperature" => &mut temperature,
+            _ => bail!("Unknown grid name : {name}"),
+        };
+        ensure!(slot.is_none(), "Volume file has more than one {name} grid");
+        *slot = Some(VoxelGrid::sparse(dims, voxels).with_context(|| format!("In grid {name}"))?);
+    }
+
+    Ok(VolumeGrids {
+        density: density.context("Volume file has no density grid")?,
+        emission,
+        temperature,
+    })
+}
+
+/// Heterogeneous participating medium defined by voxel grids stretched over `bounds`.
+///
+/// Free-flight distances are sampled with delta tracking, so the medium plugs into
+/// the regular `hit` / `scatter` recursion: a real collision is reported as a hit
+/// with an isotropic material. Shadow and occlusion rays get their transmittance
+/// from ratio tracking instead, which never stops early and so is less noisy.
+pub struct GridVolume {
+    pub bounds: Aabb,
+    pub density: VoxelGrid,
+    pub emission: Option<VoxelGrid>,
+    pub temperature: Option<VoxelGrid>,
+    pub density_scale: f64,
+    pub albedo: Rgb,
+    pub emission_color: Rgb,
+    pub emission_scale: f64,
+    pub temperature_scale: f64,
+}
+
+impl GridVolume {
+    pub fn new(bounds: Aabb, grids: VolumeGrids) -> Self {
+        Self {
+            bounds,
+            density: grids.density,
+            emission: grids.emission,
+            temperature: grids.temperature,
+            density_scale: 1.0,
+            albedo: Rgb::new(0.8, 0.8, 0.8),
+            emission_color: Rgb::new(1.0, 1.0, 1.0),
+            emission_scale: 1.0,
+            temperature_scale: 1.0,
+        }
+    }
+
+    fn to_grid_space(&self, p: Point3) -> Vec3 {
+        let rel = p - self.bounds.min;
+        let size = self.bounds.size();
+        Vec3::new(rel.x / size.x, rel.y / size.y, rel.z / size.z)
+    }
+
+    fn density_at(&self, uvw: Vec3) -> f64 {
+        self.density_scale * self.density.sample(uvw)
+    }
+
+    fn emission_at(&self, uvw: Vec3) -> Rgb {
+        let mut emitted = Rgb::BLACK;
+
+        if let Some(grid) = &self.emission {
+            emitted = emitted + self.emission_scale * grid.sample(uvw) * self.emission_color;
+        }
+
+        if let Some(grid) = &self.temperature {
+            let t = grid.sample(uvw);
+            emitted = emitted + self.temperature_scale * blackbody_rgb(t);
+        }
+
+        emitted
+    }
+
+    fn majorant(&self) -> f64 {
+        self.density_scale * self.density.max_value()
+    }
+
+    /// Delta tracking is stochastic but `hit` has no RNG parameter, so
+    /// each query seeds its own generator from the ray itself, hashed with
+    /// `sampler::hash` so the seeds don't change between Rust releases.
+    fn ray_rng(ray: &Ray) -> SmallRng {
+        let bits = |v: &Vec3| [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+        seeded_rng(&[bits(ray.origin()), bits(ray.direction())].concat())
+    }
+}
+
+impl Hittable for GridVolume {
+    fn hit(&self, ray: &Ray, ray_range: ops::Range<f64>) -> Option<HitRecord> {
+        let inside = self.bounds.hit(ray, ray_range)?;
+        let majorant = self.majorant();
+
+        if majorant <= 0.0 {
+            return None;
+        }
+
+        let mut rng = Self::ray_rng(ray);
+        let dir_len = ray.direction().len();
+        let mut t = inside.start;
+
+        // Delta tracking: sample tentative collisions against the majorant and
+        // accept each with probability density / majorant.
+        loop {
+            t -= f64::ln(1.0 - rng.random::<f64>()) / (majorant * dir_len);
+            if t >= inside.end {
+                return None;
+            }
+
+            let p = ray.at(t);
+            let uvw = self.to_grid_space(p);
+
+            if rng.random::<f64>() * majorant < self.density_at(uvw) {
+                // Absorbed paths emit, scattered paths continue: weight the
+                // emission by the absorption probability.
+                let absorption = Rgb::new(1.0, 1.0, 1.0) - self.albedo;
+                let mat = Material::Isotropic {
+                    albedo: self.albedo,
+                    emission: absorption * self.emission_at(uvw),
+                };
+
+                return Some(HitRecord::new(
+                    p,
+                    -norm(*ray.direction()),
+                    t,
+                    (uvw.x, uvw.y),
+                    true,
+                    mat,
+                ));
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, ray_range: ops::Range<f64>) -> f64 {
+        let Some(inside) = self.bounds.hit(ray, ray_range) else {
+            return 1.0;
+        };
+        let majorant = self.majorant();
+
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+
+        let mut rng = Self::ray_rng(ray);
+        let dir_len = ray.direction().len();
+        let mut t = inside.start;
+        let mut transmittance = 1.0;
+
+        // Ratio tracking: instead of stopping at the first real collision,
+        // attenuate by the null-collision probability at every tentative one.
+        loop {
+            t -= f64::ln(1.0 - rng.random::<f64>()) / (majorant * dir_len);
+            if t >= inside.end {
+                return transmittance;
+            }
+
+            let uvw = self.to_grid_space(ray.at(t));
+            transmittance *= 1.0 - self.density_at(uvw) / majorant;
+        }
+    }
+}